@@ -0,0 +1,835 @@
+use indexmap::IndexMap;
+use sqlparser::ast::{FunctionDesc, Statement};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use crate::source_code::DatabaseObject;
+use crate::utils::{map_schema_names, SqlDialect};
+
+/// Object types whose changes can be dropped and re-deployed without losing data.
+///
+/// Every other object type (tables, sequences, ...) is considered stateful: its changes must be
+/// applied incrementally and only once.
+const STATELESS_OBJECT_TYPES: &[&str] = &["view", "function", "sp", "procedure"];
+
+/// Returns `true` if the changes of the given object type are stateless.
+pub fn is_stateless(object_type: &str) -> bool {
+    STATELESS_OBJECT_TYPES.contains(&object_type)
+}
+
+/// Returns `true` if the deployed changes of the given object type can be dropped and deployed
+/// again when a change they depend on is redeployed. Triggers hold no data and are recreated with
+/// the stateless changes.
+fn is_recreatable(object_type: &str) -> bool {
+    is_stateless(object_type) || object_type == "trigger"
+}
+
+/// The result of comparing a change in the source code with the deploy log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    /// The hashes match in the source code and the deploy log.
+    Unchanged,
+    /// The change is in the source code, but not in the deploy log.
+    New,
+    /// The hashes differ between the source code and the deploy log.
+    Modified,
+    /// The change is in the deploy log, but not in the source code.
+    Removed,
+}
+
+/// The action to take on the environment for a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction {
+    /// Nothing to do, the change is already deployed.
+    NoAction,
+    /// Deploy the change.
+    Deploy,
+    /// Drop the deployed object and deploy the change again.
+    Redeploy,
+    /// Drop the deployed object.
+    Drop,
+    /// The change cannot be applied, the source code must be fixed.
+    Error,
+}
+
+impl fmt::Display for ChangeAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            ChangeAction::NoAction => "none",
+            ChangeAction::Deploy => "deploy",
+            ChangeAction::Redeploy => "redeploy",
+            ChangeAction::Drop => "drop",
+            ChangeAction::Error => "error",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+/// A change of the changeset with its status and the action to take.
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// The `schema.type.object.change` key of the change.
+    pub key: String,
+    /// The result of comparing the source code with the deploy log.
+    pub status: ChangeStatus,
+    /// The action to take on the environment.
+    pub action: ChangeAction,
+    /// The change from the source code, or the deploy log entry for removed changes.
+    pub object: DatabaseObject,
+    /// The key of the redeployed change that causes this change to be dropped and recreated.
+    pub cause: Option<String>,
+}
+
+impl Change {
+    /// Returns the SQL statements that have to be executed, in order, to apply the change.
+    ///
+    /// Removed changes are dropped with the rollback recorded in the deploy log when there is one,
+    /// and with a `DROP` statement derived from the object otherwise. The changes recreated for a
+    /// redeployed change are always dropped with the derived `DROP` statement, like the redeployed
+    /// change itself, since a hand-written rollback does not have to drop the object.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the object has to be dropped without a rollback and no
+    /// `DROP` statement can be derived for its object type.
+    pub fn statements(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(match self.action {
+            ChangeAction::Deploy => vec![self.object.value.clone()],
+            ChangeAction::Redeploy => {
+                vec![drop_statement(&self.object)?, self.object.value.clone()]
+            }
+            ChangeAction::Drop => match &self.object.rollback_content {
+                Some(rollback) if self.cause.is_none() => vec![rollback.clone()],
+                _ => vec![drop_statement(&self.object)?],
+            },
+            ChangeAction::NoAction | ChangeAction::Error => vec![],
+        })
+    }
+
+    /// Returns a description of the problem for changes that cannot be applied.
+    pub fn error_message(&self) -> Option<String> {
+        match (self.action, self.status) {
+            (ChangeAction::Error, ChangeStatus::Modified) => Some(format!(
+                "Change '{}' was modified after being deployed, stateful changes cannot be modified",
+                self.key
+            )),
+            (ChangeAction::Error, ChangeStatus::Removed) => Some(format!(
+                "Change '{}' was removed from the source code after being deployed, stateful changes cannot be removed",
+                self.key
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The ordered list of changes between the source code and the deploy log.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    /// Every change of the source code and the deploy log, in execution order.
+    pub changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    /// Compares the source code with the deploy log and classifies each change.
+    ///
+    /// Changes are compared by key and content hash, and the action of each change follows the
+    /// stateful and stateless rules:
+    ///
+    /// | Hash Comparison                 | Stateful  | Stateless |
+    /// |---------------------------------|-----------|-----------|
+    /// | Hashes match                    | No action | No action |
+    /// | In source code, not deploy log  | Deploy    | Deploy    |
+    /// | In deploy log, not source code  | Error     | Drop      |
+    /// | Hashes differ                   | Error     | Redeploy  |
+    ///
+    /// Removed changes come first, in reverse deploy order, followed by the source code changes in
+    /// the order they were given.
+    ///
+    /// A redeployed change may be referenced by deployed stateless changes, e.g. a view selecting
+    /// from a modified view, which the database would refuse to keep or to replace. These
+    /// dependents are dropped, the most dependent first, before the first change that causes their
+    /// recreation, and deployed again at their place in the execution order. Stateful dependents
+    /// cannot be recreated and are only reported with a warning.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_code` - The changes read from the source code, in execution order.
+    /// * `deploy_log` - The changes read from the deploy log of the target database.
+    pub fn compute(
+        source_code: &IndexMap<String, DatabaseObject>,
+        deploy_log: &IndexMap<String, DatabaseObject>,
+    ) -> ChangeSet {
+        let mut changes = Vec::new();
+
+        for (key, deployed) in deploy_log.iter().rev() {
+            if source_code.contains_key(key) {
+                continue;
+            }
+            let action = if is_stateless(&deployed.object_type) {
+                ChangeAction::Drop
+            } else {
+                ChangeAction::Error
+            };
+            changes.push(Change {
+                key: key.clone(),
+                status: ChangeStatus::Removed,
+                action,
+                object: deployed.clone(),
+                cause: None,
+            });
+        }
+
+        for (key, object) in source_code {
+            let (status, action) = match deploy_log.get(key) {
+                None => (ChangeStatus::New, ChangeAction::Deploy),
+                Some(deployed) if deployed.content_hash == object.content_hash => {
+                    (ChangeStatus::Unchanged, ChangeAction::NoAction)
+                }
+                Some(_) if is_stateless(&object.object_type) => {
+                    (ChangeStatus::Modified, ChangeAction::Redeploy)
+                }
+                Some(_) => (ChangeStatus::Modified, ChangeAction::Error),
+            };
+            changes.push(Change {
+                key: key.clone(),
+                status,
+                action,
+                object: object.clone(),
+                cause: None,
+            });
+        }
+
+        let recreated = recreated_dependents(source_code, deploy_log, &changes);
+        if !recreated.is_empty() {
+            changes = expand_recreated(changes, &recreated);
+        }
+
+        ChangeSet { changes }
+    }
+
+    /// Returns the changes that have to be applied to the environment, in execution order.
+    pub fn pending(&self) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(|change| !matches!(change.action, ChangeAction::NoAction | ChangeAction::Error))
+    }
+
+    /// Returns the changes that cannot be applied to the environment.
+    pub fn errors(&self) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(|change| change.action == ChangeAction::Error)
+    }
+
+    /// Returns `true` if there is nothing to apply to the environment.
+    pub fn is_empty(&self) -> bool {
+        self.pending().next().is_none()
+    }
+
+    /// Renders the pending changes and the errors as a reviewable SQL script, in execution order.
+    ///
+    /// Each change is introduced by a comment with its position, action and key, followed by the
+    /// exact statements that would be executed, with the schema names of `schema_mapping` replaced
+    /// by their physical schema names, parsing them with `dialect`. Errors are rendered as comments,
+    /// so the script stays valid SQL.
+    pub fn to_script(
+        &self,
+        schema_mapping: &HashMap<String, String>,
+        dialect: SqlDialect,
+    ) -> String {
+        let pending = self.pending().count();
+        let errors = self.errors().count();
+        let mut script = format!(
+            "-- Oxigration plan: {} changes to apply, {} errors\n",
+            pending, errors
+        );
+
+        let planned = self
+            .changes
+            .iter()
+            .filter(|change| change.action != ChangeAction::NoAction);
+        for (position, change) in planned.enumerate() {
+            script.push_str(&format!(
+                "\n-- [{}] {} {}",
+                position + 1,
+                change.action,
+                change.key
+            ));
+            if let Some(cause) = &change.cause {
+                script.push_str(&format!(" (recreated for {})", cause));
+            }
+            script.push('\n');
+            if let Some(error) = change.error_message() {
+                script.push_str(&format!("-- {}\n", error));
+            }
+            let statements = change.statements().and_then(|statements| {
+                statements
+                    .iter()
+                    .map(|statement| map_schema_names(statement, schema_mapping, dialect))
+                    .collect::<Result<Vec<_>, _>>()
+            });
+            match statements {
+                Ok(statements) => {
+                    for statement in statements {
+                        script.push_str(statement.trim_end());
+                        script.push('\n');
+                    }
+                }
+                Err(error) => script.push_str(&format!("-- {}\n", error)),
+            }
+        }
+
+        script
+    }
+}
+
+/// Computes the changeset between the source code and the deploy log.
+///
+/// # Arguments
+///
+/// * `source_code` - The changes read from the source code, in execution order.
+/// * `deploy_log` - The changes read from the deploy log of the target database.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(ChangeSet)` with the ordered changes if every change can be applied.
+/// * `Err(Box<dyn Error>)` if a stateful change was modified or removed after being deployed.
+pub fn compute_changeset(
+    source_code: &IndexMap<String, DatabaseObject>,
+    deploy_log: &IndexMap<String, DatabaseObject>,
+) -> Result<ChangeSet, Box<dyn Error>> {
+    let changeset = ChangeSet::compute(source_code, deploy_log);
+
+    let errors: Vec<String> = changeset
+        .errors()
+        .filter_map(|change| change.error_message())
+        .collect();
+    if !errors.is_empty() {
+        for error in &errors {
+            log::error!("{}", error);
+        }
+        return Err(errors.join("\n").into());
+    }
+
+    Ok(changeset)
+}
+
+/// Finds the deployed stateless changes and triggers that have to be recreated because a change
+/// they depend on is redeployed.
+///
+/// The reverse dependency graph is walked from each redeployed change, and every dependent found
+/// is mapped to the redeployed change that causes its recreation. Changes of the same object are
+/// independent statements, e.g. function overloads, and do not recreate each other. A modified
+/// trigger stays an error and is not recreated.
+///
+/// # Returns
+///
+/// The keys of the changes to recreate with the key of their cause, in discovery order.
+fn recreated_dependents(
+    source_code: &IndexMap<String, DatabaseObject>,
+    deploy_log: &IndexMap<String, DatabaseObject>,
+    changes: &[Change],
+) -> IndexMap<String, String> {
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (key, object) in source_code {
+        for dependency in &object.dependency_keys {
+            dependents
+                .entry(dependency.as_str())
+                .or_default()
+                .push(key.as_str());
+        }
+    }
+
+    let errors: HashSet<&str> = changes
+        .iter()
+        .filter(|change| change.action == ChangeAction::Error)
+        .map(|change| change.key.as_str())
+        .collect();
+
+    let mut recreated: IndexMap<String, String> = IndexMap::new();
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut queue: VecDeque<(&str, &str)> = changes
+        .iter()
+        .filter(|change| change.action == ChangeAction::Redeploy)
+        .map(|change| (change.key.as_str(), change.key.as_str()))
+        .collect();
+    visited.extend(queue.iter().map(|(key, _)| *key));
+
+    while let Some((key, cause)) = queue.pop_front() {
+        let object = &source_code[key];
+        for &dependent in dependents.get(key).into_iter().flatten() {
+            let dependent_object = &source_code[dependent];
+            if dependent_object.schema_name == object.schema_name
+                && dependent_object.object_type == object.object_type
+                && dependent_object.object_name == object.object_name
+            {
+                continue;
+            }
+            if !is_recreatable(&dependent_object.object_type) {
+                log::warn!(
+                    "Change '{}' depends on '{}', which is redeployed, but stateful changes cannot be recreated",
+                    dependent,
+                    key
+                );
+                continue;
+            }
+            if dependent == cause || recreated.contains_key(dependent) || errors.contains(dependent)
+            {
+                continue;
+            }
+            if deploy_log.contains_key(dependent) {
+                recreated.insert(dependent.to_string(), cause.to_string());
+            }
+            if visited.insert(dependent) {
+                queue.push_back((dependent, cause));
+            }
+        }
+    }
+
+    recreated
+}
+
+/// Inserts the drops of the recreated changes and turns their own entries into deployments.
+///
+/// The drops are placed in reverse execution order before the first change that causes a
+/// recreation or is recreated.
+fn expand_recreated(changes: Vec<Change>, recreated: &IndexMap<String, String>) -> Vec<Change> {
+    let causes: HashSet<&str> = recreated.values().map(String::as_str).collect();
+    let first = changes
+        .iter()
+        .position(|change| {
+            causes.contains(change.key.as_str()) || recreated.contains_key(&change.key)
+        })
+        .unwrap_or(0);
+
+    let drops: Vec<Change> = changes
+        .iter()
+        .rev()
+        .filter_map(|change| {
+            let cause = recreated.get(&change.key)?;
+            Some(Change {
+                key: change.key.clone(),
+                status: change.status,
+                action: ChangeAction::Drop,
+                object: change.object.clone(),
+                cause: Some(cause.clone()),
+            })
+        })
+        .collect();
+
+    let mut expanded = Vec::with_capacity(changes.len() + drops.len());
+    let mut drops = Some(drops);
+    for (position, mut change) in changes.into_iter().enumerate() {
+        if position == first {
+            expanded.extend(drops.take().into_iter().flatten());
+        }
+        if let Some(cause) = recreated.get(&change.key) {
+            change.action = ChangeAction::Deploy;
+            change.cause = Some(cause.clone());
+        }
+        expanded.push(change);
+    }
+    expanded
+}
+
+/// Builds the statement that drops the object deployed by a stateless change or a trigger.
+///
+/// Functions and procedures are dropped with their argument types when the change was parsed, so
+/// that only the matching overload is dropped. Triggers are dropped on their table, which is only
+/// known when the change was parsed.
+///
+/// # Errors
+///
+/// This function returns an error if the change was not parsed and its object type has no `DROP`
+/// statement.
+fn drop_statement(object: &DatabaseObject) -> Result<String, Box<dyn Error>> {
+    let statement = match &object.parsed_content {
+        Some(Statement::CreateFunction { name, args, .. }) => format!(
+            "DROP FUNCTION IF EXISTS {};",
+            FunctionDesc {
+                name: name.clone(),
+                args: args.clone(),
+            }
+        ),
+        Some(Statement::CreateProcedure { name, params, .. }) => format!(
+            "DROP PROCEDURE IF EXISTS {}({});",
+            name,
+            params
+                .iter()
+                .flatten()
+                .map(|param| param.data_type.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Some(Statement::CreateView { name, .. }) => format!("DROP VIEW IF EXISTS {};", name),
+        Some(Statement::CreateTrigger {
+            name, table_name, ..
+        }) => format!("DROP TRIGGER IF EXISTS {} ON {};", name, table_name),
+        _ => {
+            let keyword = match object.object_type.as_str() {
+                "view" => "VIEW",
+                "sp" | "procedure" => "PROCEDURE",
+                "function" => "FUNCTION",
+                object_type => {
+                    return Err(format!(
+                        "Cannot drop change '{}', objects of type '{}' have no DROP statement",
+                        object.change_name, object_type
+                    )
+                    .into())
+                }
+            };
+            format!("DROP {} IF EXISTS {};", keyword, object.object_name)
+        }
+    };
+    Ok(statement)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    /// Builds a change from its `schema.type.object.change` key and its content.
+    pub(crate) fn object(key: &str, value: &str) -> DatabaseObject {
+        let mut object = DatabaseObject::new(
+            key.to_string(),
+            value.to_string(),
+            HashSet::new(),
+            HashMap::new(),
+            None,
+        );
+        let parts: Vec<&str> = key.split('.').collect();
+        object.schema_name = parts[0].to_string();
+        object.object_type = parts[1].to_string();
+        object.object_name = parts[2].to_string();
+        object
+    }
+
+    /// Keys the changes by their key, in the order they were given.
+    pub(crate) fn objects(objects: Vec<DatabaseObject>) -> IndexMap<String, DatabaseObject> {
+        objects
+            .into_iter()
+            .map(|object| (object.change_name.clone(), object))
+            .collect()
+    }
+
+    #[test]
+    fn test_new_and_unchanged_changes() {
+        let source = objects(vec![
+            object("s.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("s.table.t2.change0", "CREATE TABLE t2 (id INT);"),
+        ]);
+        let log = objects(vec![object(
+            "s.table.t1.change0",
+            "CREATE TABLE t1 (id INT);",
+        )]);
+
+        let changeset = compute_changeset(&source, &log).unwrap();
+        assert_eq!(changeset.changes.len(), 2);
+        assert_eq!(changeset.changes[0].status, ChangeStatus::Unchanged);
+        assert_eq!(changeset.changes[0].action, ChangeAction::NoAction);
+        assert_eq!(changeset.changes[1].status, ChangeStatus::New);
+        assert_eq!(changeset.changes[1].action, ChangeAction::Deploy);
+
+        let pending: Vec<&str> = changeset.pending().map(|c| c.key.as_str()).collect();
+        assert_eq!(pending, vec!["s.table.t2.change0"]);
+    }
+
+    #[test]
+    fn test_modified_stateful_change_is_an_error() {
+        let source = objects(vec![object(
+            "s.table.t1.change0",
+            "CREATE TABLE t1 (id BIGINT);",
+        )]);
+        let log = objects(vec![object(
+            "s.table.t1.change0",
+            "CREATE TABLE t1 (id INT);",
+        )]);
+
+        let changeset = ChangeSet::compute(&source, &log);
+        assert_eq!(changeset.changes[0].status, ChangeStatus::Modified);
+        assert_eq!(changeset.changes[0].action, ChangeAction::Error);
+
+        let error = compute_changeset(&source, &log).unwrap_err().to_string();
+        assert!(error.contains("Change 's.table.t1.change0' was modified"));
+    }
+
+    #[test]
+    fn test_removed_stateful_change_is_an_error() {
+        let source = objects(vec![]);
+        let log = objects(vec![object(
+            "s.table.t1.change0",
+            "CREATE TABLE t1 (id INT);",
+        )]);
+
+        let error = compute_changeset(&source, &log).unwrap_err().to_string();
+        assert!(error.contains("Change 's.table.t1.change0' was removed"));
+    }
+
+    #[test]
+    fn test_stateless_changes_are_redeployed_and_dropped() {
+        let source = objects(vec![object(
+            "s.view.v1.root0",
+            "CREATE VIEW v1 AS SELECT 2;",
+        )]);
+        let log = objects(vec![
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 1;"),
+            object("s.view.v2.root0", "CREATE VIEW v2 AS SELECT 1;"),
+        ]);
+
+        let changeset = compute_changeset(&source, &log).unwrap();
+        assert_eq!(changeset.changes.len(), 2);
+
+        let dropped = &changeset.changes[0];
+        assert_eq!(dropped.key, "s.view.v2.root0");
+        assert_eq!(dropped.status, ChangeStatus::Removed);
+        assert_eq!(dropped.action, ChangeAction::Drop);
+        assert_eq!(
+            dropped.statements().unwrap(),
+            vec!["DROP VIEW IF EXISTS v2;"]
+        );
+
+        let redeployed = &changeset.changes[1];
+        assert_eq!(redeployed.status, ChangeStatus::Modified);
+        assert_eq!(redeployed.action, ChangeAction::Redeploy);
+        assert_eq!(redeployed.statements().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_removed_change_is_dropped_with_its_rollback() {
+        let mut deployed = object("s.view.v2.root0", "CREATE VIEW v2 AS SELECT 1;");
+        deployed.rollback_content = Some("DROP VIEW v2 CASCADE;".to_string());
+
+        let changeset = compute_changeset(&objects(vec![]), &objects(vec![deployed])).unwrap();
+        assert_eq!(
+            changeset.changes[0].statements().unwrap(),
+            vec!["DROP VIEW v2 CASCADE;"]
+        );
+    }
+
+    #[test]
+    fn test_drop_statement_of_function_overload() {
+        let sql = "CREATE FUNCTION f1(var1 integer) RETURNS integer LANGUAGE plpgsql AS 'BEGIN RETURN 1; END;';";
+        let mut function = object("s.function.f1.root1", sql);
+        function.parsed_content = Some(
+            sqlparser::parser::Parser::parse_sql(&sqlparser::dialect::PostgreSqlDialect {}, sql)
+                .unwrap()
+                .remove(0),
+        );
+
+        assert_eq!(
+            drop_statement(&function).unwrap(),
+            "DROP FUNCTION IF EXISTS f1(var1 INTEGER);"
+        );
+    }
+
+    fn depends_on(mut object: DatabaseObject, dependencies: &[&str]) -> DatabaseObject {
+        object.dependency_keys = dependencies.iter().map(|key| key.to_string()).collect();
+        object
+    }
+
+    #[test]
+    fn test_dependents_of_redeployed_change_are_recreated() {
+        let source = objects(vec![
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 2 AS id;"),
+            depends_on(
+                object("s.view.v2.root0", "CREATE VIEW v2 AS SELECT * FROM v1;"),
+                &["s.view.v1.root0"],
+            ),
+            depends_on(
+                object("s.view.v3.root0", "CREATE VIEW v3 AS SELECT * FROM v2;"),
+                &["s.view.v2.root0"],
+            ),
+            depends_on(
+                object("s.view.v4.root0", "CREATE VIEW v4 AS SELECT * FROM v1;"),
+                &["s.view.v1.root0"],
+            ),
+            object("s.view.v5.root0", "CREATE VIEW v5 AS SELECT 5;"),
+        ]);
+        let mut log = objects(vec![
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 1 AS id;"),
+            object("s.view.v2.root0", "CREATE VIEW v2 AS SELECT * FROM v1;"),
+            object("s.view.v3.root0", "CREATE VIEW v3 AS SELECT * FROM v2;"),
+            object("s.view.v5.root0", "CREATE VIEW v5 AS SELECT 5;"),
+        ]);
+        // A hand-written rollback that does not drop the view is not used to recreate it
+        log["s.view.v3.root0"].rollback_content =
+            Some("CREATE OR REPLACE VIEW v3 AS SELECT 1 AS id;".to_string());
+
+        let changeset = compute_changeset(&source, &log).unwrap();
+        let planned: Vec<(&str, ChangeAction, Option<&str>)> = changeset
+            .pending()
+            .map(|c| (c.key.as_str(), c.action, c.cause.as_deref()))
+            .collect();
+        let cause = Some("s.view.v1.root0");
+        assert_eq!(
+            planned,
+            vec![
+                ("s.view.v3.root0", ChangeAction::Drop, cause),
+                ("s.view.v2.root0", ChangeAction::Drop, cause),
+                ("s.view.v1.root0", ChangeAction::Redeploy, None),
+                ("s.view.v2.root0", ChangeAction::Deploy, cause),
+                ("s.view.v3.root0", ChangeAction::Deploy, cause),
+                ("s.view.v4.root0", ChangeAction::Deploy, None),
+            ]
+        );
+        assert_eq!(
+            changeset.changes[0].statements().unwrap(),
+            vec!["DROP VIEW IF EXISTS v3;"]
+        );
+        assert_eq!(
+            changeset.changes[1].statements().unwrap(),
+            vec!["DROP VIEW IF EXISTS v2;"]
+        );
+        assert_eq!(changeset.changes[3].status, ChangeStatus::Unchanged);
+
+        let script = changeset.to_script(&HashMap::new(), SqlDialect::PostgreSql);
+        assert!(script.contains("\n-- [1] drop s.view.v3.root0 (recreated for s.view.v1.root0)\n"));
+    }
+
+    #[test]
+    fn test_stateful_dependents_and_same_object_changes_are_not_recreated() {
+        let source = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() ..."),
+            depends_on(
+                object("s.function.f1.root1", "CREATE FUNCTION f1(a INT) ..."),
+                &["s.function.f1.root0"],
+            ),
+            depends_on(
+                object(
+                    "s.table.t1.change0",
+                    "CREATE TABLE t1 (id INT DEFAULT f1());",
+                ),
+                &["s.function.f1.root0"],
+            ),
+        ]);
+        let log = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() old"),
+            object("s.function.f1.root1", "CREATE FUNCTION f1(a INT) ..."),
+            object(
+                "s.table.t1.change0",
+                "CREATE TABLE t1 (id INT DEFAULT f1());",
+            ),
+        ]);
+
+        let changeset = compute_changeset(&source, &log).unwrap();
+        let planned: Vec<(&str, ChangeAction)> = changeset
+            .pending()
+            .map(|c| (c.key.as_str(), c.action))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![("s.function.f1.root0", ChangeAction::Redeploy)]
+        );
+    }
+
+    #[test]
+    fn test_triggers_of_redeployed_function_are_recreated() {
+        let trigger_sql =
+            "CREATE TRIGGER trg1 BEFORE INSERT ON s.t1 FOR EACH ROW EXECUTE FUNCTION s.f1();";
+        let mut trigger = depends_on(
+            object("s.trigger.trg1.change0", trigger_sql),
+            &["s.function.f1.root0"],
+        );
+        trigger.parsed_content = Some(
+            sqlparser::parser::Parser::parse_sql(
+                &sqlparser::dialect::PostgreSqlDialect {},
+                trigger_sql,
+            )
+            .unwrap()
+            .remove(0),
+        );
+        let source = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() new"),
+            trigger,
+        ]);
+        let log = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() old"),
+            object("s.trigger.trg1.change0", trigger_sql),
+        ]);
+
+        let changeset = compute_changeset(&source, &log).unwrap();
+        let planned: Vec<(&str, ChangeAction)> = changeset
+            .pending()
+            .map(|c| (c.key.as_str(), c.action))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                ("s.trigger.trg1.change0", ChangeAction::Drop),
+                ("s.function.f1.root0", ChangeAction::Redeploy),
+                ("s.trigger.trg1.change0", ChangeAction::Deploy),
+            ]
+        );
+        assert_eq!(
+            changeset.changes[0].statements().unwrap(),
+            vec!["DROP TRIGGER IF EXISTS trg1 ON s.t1;"]
+        );
+        assert_eq!(
+            changeset.changes[2].statements().unwrap(),
+            vec![trigger_sql]
+        );
+    }
+
+    #[test]
+    fn test_modified_trigger_of_redeployed_function_is_an_error() {
+        let source = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() new"),
+            depends_on(
+                object("s.trigger.trg1.change0", "CREATE TRIGGER trg1 ... new"),
+                &["s.function.f1.root0"],
+            ),
+        ]);
+        let log = objects(vec![
+            object("s.function.f1.root0", "CREATE FUNCTION f1() old"),
+            object("s.trigger.trg1.change0", "CREATE TRIGGER trg1 ... old"),
+        ]);
+
+        let error = compute_changeset(&source, &log).unwrap_err().to_string();
+        assert!(error.contains("Change 's.trigger.trg1.change0' was modified after being deployed"));
+    }
+
+    #[test]
+    fn test_drop_statement_of_unknown_object_type_is_an_error() {
+        let deployed = object("s.synonym.syn1.root0", "CREATE SYNONYM syn1 FOR t1;");
+        let change = Change {
+            key: deployed.change_name.clone(),
+            status: ChangeStatus::Removed,
+            action: ChangeAction::Drop,
+            object: deployed,
+            cause: None,
+        };
+
+        let error = change.statements().unwrap_err().to_string();
+        assert!(error.contains("objects of type 'synonym' have no DROP statement"));
+    }
+
+    #[test]
+    fn test_changeset_script() {
+        let source = objects(vec![
+            object("s.table.t1.change0", "CREATE TABLE t1 (id BIGINT);"),
+            object("s.table.t2.change0", "CREATE TABLE t2 (id INT);"),
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 2;"),
+        ]);
+        let log = objects(vec![
+            object("s.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 1;"),
+        ]);
+
+        let script =
+            ChangeSet::compute(&source, &log).to_script(&HashMap::new(), SqlDialect::PostgreSql);
+        assert_eq!(
+            script,
+            "-- Oxigration plan: 2 changes to apply, 1 errors\n\
+             \n-- [1] error s.table.t1.change0\n\
+             -- Change 's.table.t1.change0' was modified after being deployed, stateful changes cannot be modified\n\
+             \n-- [2] deploy s.table.t2.change0\n\
+             CREATE TABLE t2 (id INT);\n\
+             \n-- [3] redeploy s.view.v1.root0\n\
+             DROP VIEW IF EXISTS v1;\n\
+             CREATE VIEW v1 AS SELECT 2;\n"
+        );
+    }
+}
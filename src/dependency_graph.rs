@@ -0,0 +1,728 @@
+use crate::source_code::{DatabaseObject, Reference};
+use indexmap::IndexMap;
+use std::error::Error;
+use std::fmt;
+
+/// Why a change depends on another change.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DependencyReason {
+    /// The dependency is a previous change of the same file.
+    PreviousChange,
+    /// The dependency is declared with the `depends` attribute, with the declared name.
+    Depends(String),
+    /// The statements of the change reference the object of the dependency, with the referenced
+    /// name.
+    Reference(String),
+}
+
+impl fmt::Display for DependencyReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependencyReason::PreviousChange => write!(f, "previous change of the file"),
+            DependencyReason::Depends(name) => write!(f, "depends={}", name),
+            DependencyReason::Reference(name) => write!(f, "reference to '{}'", name),
+        }
+    }
+}
+
+/// An edge of the dependency graph, from a change to a change that depends on it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DependencyEdge<'a> {
+    /// The key of the change that must be executed first.
+    pub dependency: &'a str,
+    /// The key of the change that depends on it.
+    pub dependent: &'a str,
+    /// Why the dependent change depends on the other one.
+    pub reason: DependencyReason,
+}
+
+/// How close a symbol is to the change that refers to it. An unqualified name resolves to the
+/// closest symbols, so that a change of the same object wins over an object of the same schema,
+/// which wins over an object of another schema.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Scope {
+    SameObject,
+    SameSchema,
+    OtherSchema,
+}
+
+/// A symbol a name resolves to: a change, or every change of an object.
+struct Symbol<'a> {
+    /// The qualified name of the symbol, used in error messages.
+    name: String,
+    /// The keys of the changes of the symbol, in source order.
+    keys: Vec<&'a str>,
+}
+
+/// The changes of one object of the source code.
+struct ObjectSymbols<'a> {
+    schema_name: &'a str,
+    object_type: &'a str,
+    object_name: &'a str,
+    /// The name of each change of the object with its key, in source order.
+    changes: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> ObjectSymbols<'a> {
+    fn is_object_of(&self, obj: Option<&DatabaseObject>) -> bool {
+        obj.is_some_and(|obj| {
+            self.schema_name == obj.schema_name
+                && self.object_type == obj.object_type
+                && self.object_name == obj.object_name
+        })
+    }
+
+    fn change(&self, change_name: &str) -> Option<Symbol<'a>> {
+        self.changes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(change_name))
+            .map(|(name, key)| Symbol {
+                name: format!(
+                    "{}.{}.{}.{}",
+                    self.schema_name, self.object_type, self.object_name, name
+                ),
+                keys: vec![*key],
+            })
+    }
+}
+
+/// The symbols of the source code, used to resolve the dependencies of the changes.
+///
+/// Every change is registered under its schema directory, object type directory, object name and
+/// change name, which is also the key of the change. Names are compared case-insensitively.
+pub struct SymbolTable<'a> {
+    /// The objects by (schema, object type, object name), in source order.
+    objects: IndexMap<(String, String, String), ObjectSymbols<'a>>,
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Registers every change of the source code.
+    pub fn new(object_info: &'a IndexMap<String, DatabaseObject>) -> Self {
+        let mut objects: IndexMap<(String, String, String), ObjectSymbols<'a>> = IndexMap::new();
+        for (key, obj) in object_info {
+            objects
+                .entry((
+                    obj.schema_name.to_lowercase(),
+                    obj.object_type.to_lowercase(),
+                    obj.object_name.to_lowercase(),
+                ))
+                .or_insert_with(|| ObjectSymbols {
+                    schema_name: &obj.schema_name,
+                    object_type: &obj.object_type,
+                    object_name: &obj.object_name,
+                    changes: Vec::new(),
+                })
+                .changes
+                .push((obj.short_change_name(), key));
+        }
+        SymbolTable { objects }
+    }
+
+    /// Resolves a dependency declared by a change, either implicitly on a previous change of its
+    /// file or with the `depends` attribute.
+    ///
+    /// The dependency is a dotted name, which is resolved as follows:
+    ///
+    /// * `change` - A change of the same object, otherwise an object or a change of another
+    ///   object of the same schema, otherwise of another schema.
+    /// * `schema.object` or `object.change` - Every change of an object of a schema, or a change of
+    ///   an object of the same schema, otherwise of another schema.
+    /// * `schema.object.change` or `schema.type.object` - A change of an object, or every change
+    ///   of an object of a given type.
+    /// * `schema.type.object.change` - The change with that key.
+    ///
+    /// A dependency on an object is a dependency on every change of that object, whatever its type.
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - The change that declares the dependency.
+    /// * `dependency` - The name of the dependency.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result` containing:
+    /// * `Ok(Vec<&str>)` - The keys of the changes the change depends on.
+    /// * `Err(String)` - If the name matches nothing, or several symbols of the same scope.
+    pub fn resolve_dependency(
+        &self,
+        obj: &DatabaseObject,
+        dependency: &str,
+    ) -> Result<Vec<&'a str>, String> {
+        match closest(self.symbols_named(Some(obj), dependency)) {
+            Ok(Some(symbol)) => Ok(symbol.keys),
+            Ok(None) => Err(format!(
+                "Dependency '{}' of change '{}' in {}:{} does not match any object or change",
+                dependency, obj.change_name, obj.file_path, obj.line
+            )),
+            Err(names) => Err(format!(
+                "Dependency '{}' of change '{}' in {}:{} is ambiguous, it matches {}",
+                dependency,
+                obj.change_name,
+                obj.file_path,
+                obj.line,
+                names.join(", ")
+            )),
+        }
+    }
+
+    /// Resolves a name given outside of any change, such as on the command line, with the rules
+    /// of `resolve_dependency`. Without a change, every schema is equally close.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result` containing:
+    /// * `Ok(Vec<&str>)` - The keys of the changes of the symbol.
+    /// * `Err(String)` - If the name matches nothing, or several symbols.
+    pub fn resolve_name(&self, name: &str) -> Result<Vec<&'a str>, String> {
+        match closest(self.symbols_named(None, name)) {
+            Ok(Some(symbol)) => Ok(symbol.keys),
+            Ok(None) => Err(format!("'{}' does not match any object or change", name)),
+            Err(names) => Err(format!(
+                "'{}' is ambiguous, it matches {}",
+                name,
+                names.join(", ")
+            )),
+        }
+    }
+
+    /// Returns the symbols a dotted name can refer to from a change, or from outside of any change.
+    fn symbols_named(&self, obj: Option<&DatabaseObject>, name: &str) -> Vec<(Scope, Symbol<'a>)> {
+        let parts: Vec<&str> = name.split('.').collect();
+        let mut symbols: Vec<(Scope, Symbol<'a>)> = Vec::new();
+        match parts.as_slice() {
+            [change_name] => {
+                for object in self.objects.values() {
+                    let scope = if object.is_object_of(obj) {
+                        Scope::SameObject
+                    } else {
+                        self.scope_of(obj, object.schema_name)
+                    };
+                    symbols.extend(object.change(change_name).map(|symbol| (scope, symbol)));
+                }
+                symbols.extend(self.objects_named(obj, None, change_name, None));
+            }
+            [first, second] => {
+                symbols.extend(self.objects_named(obj, Some(first), second, None));
+                for object in self.objects.values() {
+                    if object.object_name.eq_ignore_ascii_case(first) && !object.is_object_of(obj) {
+                        symbols.extend(
+                            object
+                                .change(second)
+                                .map(|symbol| (self.scope_of(obj, object.schema_name), symbol)),
+                        );
+                    }
+                }
+            }
+            [schema_name, second, third] => {
+                for object in self.objects.values() {
+                    if !object.schema_name.eq_ignore_ascii_case(schema_name) {
+                        continue;
+                    }
+                    if object.object_type.eq_ignore_ascii_case(second)
+                        && object.object_name.eq_ignore_ascii_case(third)
+                    {
+                        symbols.push((Scope::SameSchema, self.object_symbol(&[object])));
+                    }
+                    if object.object_name.eq_ignore_ascii_case(second) {
+                        symbols.extend(
+                            object
+                                .change(third)
+                                .map(|symbol| (Scope::SameSchema, symbol)),
+                        );
+                    }
+                }
+            }
+            [schema_name, object_type, object_name, change_name] => {
+                let object_key = (
+                    schema_name.to_lowercase(),
+                    object_type.to_lowercase(),
+                    object_name.to_lowercase(),
+                );
+                if let Some(object) = self.objects.get(&object_key) {
+                    symbols.extend(
+                        object
+                            .change(change_name)
+                            .map(|symbol| (Scope::SameSchema, symbol)),
+                    );
+                }
+            }
+            _ => {}
+        }
+        symbols
+    }
+
+    /// Resolves an object referenced by the statements of a change.
+    ///
+    /// A `schema.name` reference resolves to the object of that schema. A `name` reference
+    /// resolves to the object of the schema of the change, otherwise to the object of another
+    /// schema. Only the objects of the types of the reference kind are candidates, so a called
+    /// function never resolves to a table of the same name, nor a relation to a function. The
+    /// change depends on every change of the referenced object, so that it sees the object in
+    /// its final shape.
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - The change with the reference.
+    /// * `reference` - The referenced object, as `name` or `schema.name`, and its kind.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result` containing:
+    /// * `Ok(Vec<&str>)` - The keys of the changes of the referenced object, which is empty for a
+    ///   reference to an object that is not part of the source code (catalog tables, built-in
+    ///   functions, ...) or to the object of the change itself.
+    /// * `Err(String)` - If an unqualified name matches objects of several other schemas.
+    pub fn resolve_reference(
+        &self,
+        obj: &DatabaseObject,
+        reference: &Reference,
+    ) -> Result<Vec<&'a str>, String> {
+        let object_types = Some(reference.kind.object_types());
+        let symbols = match reference.name.split_once('.') {
+            Some((schema_name, object_name)) => {
+                self.objects_named(Some(obj), Some(schema_name), object_name, object_types)
+            }
+            None => self.objects_named(Some(obj), None, &reference.name, object_types),
+        };
+        match closest(symbols) {
+            Ok(symbol) => Ok(symbol.map(|symbol| symbol.keys).unwrap_or_default()),
+            Err(names) => Err(format!(
+                "Reference to '{}' in change '{}' in {}:{} is ambiguous, it matches {}, qualify it with its schema or declare the dependency with the 'depends' attribute",
+                reference.name,
+                obj.change_name,
+                obj.file_path,
+                obj.line,
+                names.join(", ")
+            )),
+        }
+    }
+
+    /// Returns the objects with a given name other than the object of the change, in the given
+    /// schema or in every schema, and of the given types or of any type. The objects of different
+    /// types in the same schema form a single symbol.
+    fn objects_named(
+        &self,
+        obj: Option<&DatabaseObject>,
+        schema_name: Option<&str>,
+        object_name: &str,
+        object_types: Option<&[&str]>,
+    ) -> Vec<(Scope, Symbol<'a>)> {
+        let mut by_schema: IndexMap<String, Vec<&ObjectSymbols<'a>>> = IndexMap::new();
+        for object in self.objects.values() {
+            let in_schema = match schema_name {
+                Some(schema_name) => object.schema_name.eq_ignore_ascii_case(schema_name),
+                None => true,
+            };
+            let of_type = match object_types {
+                Some(types) => types
+                    .iter()
+                    .any(|object_type| object.object_type.eq_ignore_ascii_case(object_type)),
+                None => true,
+            };
+            if in_schema
+                && of_type
+                && object.object_name.eq_ignore_ascii_case(object_name)
+                && !object.is_object_of(obj)
+            {
+                by_schema
+                    .entry(object.schema_name.to_lowercase())
+                    .or_default()
+                    .push(object);
+            }
+        }
+        by_schema
+            .into_values()
+            .map(|objects| {
+                let scope = match schema_name {
+                    Some(_) => Scope::SameSchema,
+                    None => self.scope_of(obj, objects[0].schema_name),
+                };
+                (scope, self.object_symbol(&objects))
+            })
+            .collect()
+    }
+
+    /// Returns the symbol of every change of the given objects.
+    fn object_symbol(&self, objects: &[&ObjectSymbols<'a>]) -> Symbol<'a> {
+        Symbol {
+            name: objects
+                .iter()
+                .map(|object| {
+                    format!(
+                        "{}.{}.{}",
+                        object.schema_name, object.object_type, object.object_name
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" and "),
+            keys: objects
+                .iter()
+                .flat_map(|object| object.changes.iter().map(|(_, key)| *key))
+                .collect(),
+        }
+    }
+
+    fn scope_of(&self, obj: Option<&DatabaseObject>, schema_name: &str) -> Scope {
+        match obj {
+            Some(obj) if !schema_name.eq_ignore_ascii_case(&obj.schema_name) => Scope::OtherSchema,
+            _ => Scope::SameSchema,
+        }
+    }
+}
+
+/// Returns the only symbol of the closest scope, or the names of the symbols of that scope if
+/// there are several.
+fn closest(symbols: Vec<(Scope, Symbol<'_>)>) -> Result<Option<Symbol<'_>>, Vec<String>> {
+    let Some(scope) = symbols.iter().map(|(scope, _)| *scope).min() else {
+        return Ok(None);
+    };
+    let mut closest: Vec<Symbol> = symbols
+        .into_iter()
+        .filter(|(symbol_scope, _)| *symbol_scope == scope)
+        .map(|(_, symbol)| symbol)
+        .collect();
+    if closest.len() == 1 {
+        Ok(closest.pop())
+    } else {
+        Err(closest.into_iter().map(|symbol| symbol.name).collect())
+    }
+}
+
+/// Builds the edges of the dependency graph of the source code.
+///
+/// Each edge goes from a change to a change that depends on it. The dependencies of a change are
+/// the previous changes of its file, the ones declared with the `depends` attribute (see
+/// `SymbolTable::resolve_dependency`) and the objects its statements reference (see
+/// `SymbolTable::resolve_reference`).
+///
+/// # Arguments
+///
+/// * `object_info` - The changes of the source code.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing:
+/// * `Ok(Vec<DependencyEdge>)` - The edges, with the reason of each one.
+/// * `Err(Box<dyn Error>)` - Every dependency that is unresolved or ambiguous, one per line.
+pub fn dependency_edges(
+    object_info: &IndexMap<String, DatabaseObject>,
+) -> Result<Vec<DependencyEdge<'_>>, Box<dyn Error>> {
+    let symbols = SymbolTable::new(object_info);
+    let mut edges = Vec::new();
+    let mut errors = Vec::new();
+
+    for (key, obj) in object_info {
+        let declared: Vec<&str> = obj
+            ._properties
+            .get("depends")
+            .map(|depends| depends.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        // Dependencies are sorted, so that the edges and errors are in a stable order
+        let mut dependencies: Vec<&String> = obj.dependencies.iter().collect();
+        dependencies.sort();
+        let mut references: Vec<&Reference> = obj.inferred_dependencies.iter().collect();
+        references.sort();
+
+        let resolved = dependencies
+            .into_iter()
+            .map(|dependency| {
+                let reason = if declared.contains(&dependency.as_str()) {
+                    DependencyReason::Depends(dependency.clone())
+                } else {
+                    DependencyReason::PreviousChange
+                };
+                (symbols.resolve_dependency(obj, dependency), reason)
+            })
+            .chain(references.into_iter().map(|reference| {
+                (
+                    symbols.resolve_reference(obj, reference),
+                    DependencyReason::Reference(reference.name.clone()),
+                )
+            }));
+        for (result, reason) in resolved {
+            match result {
+                Ok(dep_keys) => edges.extend(dep_keys.into_iter().map(|dep_key| DependencyEdge {
+                    dependency: dep_key,
+                    dependent: key.as_str(),
+                    reason: reason.clone(),
+                })),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(edges)
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+/// Describes a cycle of the dependency graph, so that it can be broken.
+///
+/// The message starts with the path of the cycle, where each change depends on the next one,
+/// followed by the file and reason of each of its edges and by every group of changes that depend
+/// on each other.
+///
+/// # Arguments
+///
+/// * `object_info` - The changes of the source code.
+/// * `edges` - The edges of the dependency graph.
+/// * `components` - The groups of changes that depend on each other.
+/// * `cycle` - A cycle of the graph, following the edges.
+///
+/// # Returns
+///
+/// The message of the error, starting with "Cycle detected in dependencies".
+pub fn describe_cycle(
+    object_info: &IndexMap<String, DatabaseObject>,
+    edges: &[DependencyEdge],
+    components: &[Vec<&str>],
+    cycle: &[&str],
+) -> String {
+    // The edges go from a dependency to its dependent, the path reads "depends on"
+    let path: Vec<&str> = cycle.iter().rev().copied().collect();
+    let mut lines = vec![format!(
+        "Cycle detected in dependencies: {}",
+        path.join(" -> ")
+    )];
+    for pair in path.windows(2) {
+        let (dependent, dependency) = (pair[0], pair[1]);
+        let reasons: Vec<String> = edges
+            .iter()
+            .filter(|edge| edge.dependent == dependent && edge.dependency == dependency)
+            .map(|edge| edge.reason.to_string())
+            .collect();
+        let obj = &object_info[dependent];
+        lines.push(format!(
+            "  {} ({}:{}) depends on {}: {}",
+            dependent,
+            obj.file_path,
+            obj.line,
+            dependency,
+            reasons.join(", ")
+        ));
+    }
+    for component in components {
+        lines.push(format!(
+            "Changes that depend on each other: {}",
+            component.join(", ")
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_code::ReferenceKind;
+    use std::collections::{HashMap, HashSet};
+
+    fn change(key: &str, depends: &str) -> (String, DatabaseObject) {
+        let parts: Vec<&str> = key.split('.').collect();
+        let mut properties = HashMap::new();
+        if !depends.is_empty() {
+            properties.insert("depends".to_string(), depends.to_string());
+        }
+        let mut obj = DatabaseObject::new(
+            key.to_string(),
+            String::new(),
+            HashSet::new(),
+            properties,
+            None,
+        );
+        obj.schema_name = parts[0].to_string();
+        obj.object_type = parts[1].to_string();
+        obj.object_name = parts[2].to_string();
+        obj.file_path = format!("{}/{}/{}.sql", parts[0], parts[1], parts[2]);
+        (key.to_string(), obj)
+    }
+
+    fn resolve(
+        object_info: &IndexMap<String, DatabaseObject>,
+        key: &str,
+        dependency: &str,
+    ) -> Result<Vec<String>, String> {
+        SymbolTable::new(object_info)
+            .resolve_dependency(&object_info[key], dependency)
+            .map(|keys| keys.into_iter().map(String::from).collect())
+    }
+
+    #[test]
+    fn test_dependencies_resolve_to_the_closest_symbol() {
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.t1.change0", ""),
+            change("s1.table.t1.change1", ""),
+            change("s1.table.t2.change0", ""),
+            change("s1.table.t2.change1", ""),
+            change("s1.view.v1.root0", ""),
+            change("s2.table.t1.change0", ""),
+            change("s2.table.t3.change0", ""),
+        ]
+        .into_iter()
+        .collect();
+
+        // A change name resolves to the change of the same object first
+        assert_eq!(
+            resolve(&object_info, "s1.table.t2.change1", "change0"),
+            Ok(vec!["s1.table.t2.change0".to_string()])
+        );
+        // An object name resolves to every change of the object of the same schema first
+        assert_eq!(
+            resolve(&object_info, "s1.view.v1.root0", "t1"),
+            Ok(vec![
+                "s1.table.t1.change0".to_string(),
+                "s1.table.t1.change1".to_string()
+            ])
+        );
+        // Then to the object of another schema
+        assert_eq!(
+            resolve(&object_info, "s1.view.v1.root0", "t3"),
+            Ok(vec!["s2.table.t3.change0".to_string()])
+        );
+        assert_eq!(
+            resolve(&object_info, "s1.view.v1.root0", "s2.t1"),
+            Ok(vec!["s2.table.t1.change0".to_string()])
+        );
+        assert_eq!(
+            resolve(&object_info, "s1.view.v1.root0", "t1.change1"),
+            Ok(vec!["s1.table.t1.change1".to_string()])
+        );
+        assert_eq!(
+            resolve(&object_info, "s2.table.t3.change0", "s1.table.t2"),
+            Ok(vec![
+                "s1.table.t2.change0".to_string(),
+                "s1.table.t2.change1".to_string()
+            ])
+        );
+        assert_eq!(
+            resolve(&object_info, "s2.table.t3.change0", "s1.t2.change1"),
+            Ok(vec!["s1.table.t2.change1".to_string()])
+        );
+        assert_eq!(
+            resolve(&object_info, "s2.table.t3.change0", "S1.Table.T1.Change0"),
+            Ok(vec!["s1.table.t1.change0".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_unresolved_and_ambiguous_dependencies_are_errors() {
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.table_a.change_a", ""),
+            change("s1.table.table_b.change0", ""),
+            change("s1.view.v1.root0", ""),
+            change("s2.table.t1.change0", ""),
+            change("s3.table.t1.change0", ""),
+        ]
+        .into_iter()
+        .collect();
+
+        // Names are never matched by suffix or by a segment of a key
+        let error = resolve(&object_info, "s1.view.v1.root0", "a").unwrap_err();
+        assert_eq!(
+            error,
+            "Dependency 'a' of change 's1.view.v1.root0' in s1/view/v1.sql:0 does not match any object or change"
+        );
+        assert!(resolve(&object_info, "s1.view.v1.root0", "table_a.change0").is_err());
+        assert!(resolve(&object_info, "s1.view.v1.root0", "v1").is_err());
+
+        let error = resolve(&object_info, "s1.view.v1.root0", "t1").unwrap_err();
+        assert!(error.contains("is ambiguous, it matches s2.table.t1, s3.table.t1"));
+        let error = resolve(&object_info, "s1.table.table_b.change0", "change_a");
+        assert_eq!(error, Ok(vec!["s1.table.table_a.change_a".to_string()]));
+    }
+
+    #[test]
+    fn test_references_resolve_to_objects_of_the_source_code() {
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.t1.change0", ""),
+            change("s1.table.t1.change1", ""),
+            change("s1.view.v1.root0", ""),
+            change("s2.table.t2.change0", ""),
+            change("s3.table.t2.change0", ""),
+        ]
+        .into_iter()
+        .collect();
+        let symbols = SymbolTable::new(&object_info);
+        let view = &object_info["s1.view.v1.root0"];
+        let relation = |name: &str| {
+            symbols.resolve_reference(view, &Reference::new(name, ReferenceKind::Relation))
+        };
+
+        assert_eq!(
+            relation("T1"),
+            Ok(vec!["s1.table.t1.change0", "s1.table.t1.change1"])
+        );
+        assert_eq!(relation("s3.t2"), Ok(vec!["s3.table.t2.change0"]));
+        assert_eq!(relation("pg_class"), Ok(vec![]));
+        assert_eq!(relation("v1"), Ok(vec![]));
+        assert!(relation("t2")
+            .unwrap_err()
+            .contains("qualify it with its schema"));
+    }
+
+    #[test]
+    fn test_references_resolve_to_objects_of_their_kind() {
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.audit.change0", ""),
+            change("s1.function.audit.root0", ""),
+            change("s1.sp.archive.root0", ""),
+            change("s1.table.count.change0", ""),
+            change("s1.view.v1.root0", ""),
+        ]
+        .into_iter()
+        .collect();
+        let symbols = SymbolTable::new(&object_info);
+        let view = &object_info["s1.view.v1.root0"];
+        let resolve = |name: &str, kind: ReferenceKind| {
+            symbols.resolve_reference(view, &Reference::new(name, kind))
+        };
+
+        assert_eq!(
+            resolve("audit", ReferenceKind::Relation),
+            Ok(vec!["s1.table.audit.change0"])
+        );
+        assert_eq!(
+            resolve("audit", ReferenceKind::Function),
+            Ok(vec!["s1.function.audit.root0"])
+        );
+        assert_eq!(
+            resolve("s1.archive", ReferenceKind::Function),
+            Ok(vec!["s1.sp.archive.root0"])
+        );
+        // A call of a built-in function does not depend on a table of the same name
+        assert_eq!(resolve("count", ReferenceKind::Function), Ok(vec![]));
+        assert_eq!(resolve("archive", ReferenceKind::Relation), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_dependency_edges_report_every_error() {
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.t1.change0", ""),
+            change("s1.table.t2.change0", "t1,missing1"),
+            change("s1.table.t3.change0", "missing2"),
+        ]
+        .into_iter()
+        .collect();
+
+        let error = dependency_edges(&object_info).unwrap_err().to_string();
+        assert_eq!(error.lines().count(), 2);
+        assert!(error.contains("Dependency 'missing1'"));
+        assert!(error.contains("Dependency 'missing2'"));
+
+        let object_info: IndexMap<String, DatabaseObject> = [
+            change("s1.table.t1.change0", ""),
+            change("s1.table.t2.change0", "t1"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            dependency_edges(&object_info).unwrap(),
+            vec![DependencyEdge {
+                dependency: "s1.table.t1.change0",
+                dependent: "s1.table.t2.change0",
+                reason: DependencyReason::Depends("t1".to_string()),
+            }]
+        );
+    }
+}
@@ -1,20 +1,31 @@
 use indexmap::IndexMap;
-use sqlx::{query, query_scalar, AnyPool, Executor};
-use std::env;
+use sqlx::any::AnyRow;
+use sqlx::{query, query_scalar, Any, AnyPool, Executor, Row, Transaction};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::sync::atomic::Ordering;
+use std::fmt;
 
 use crate::source_code::DatabaseObject;
-use crate::utils::{format_query_with_schema, SCHEMA_SUPPORT};
+use crate::utils::{format_query_with_schema, DatabaseKind, MetadataContext};
+
+/// The version of the layout of the deploy log tables, recorded as the `metadata_version` setting
+/// and increased with every new entry of `metadata_upgrades`.
+pub const METADATA_VERSION: i64 = 3;
 
 /// This function initializes the deploy log and the configuration settings in the database.
 /// It performs the following steps:
 ///
-/// 1. Creates a deploy schema if it does not already exist.
+/// 1. Creates the metadata schema (`oxigration` unless the environment sets `metadata_schema`) if it
+///    does not already exist.
 /// 2. Creates a `deploy_log` table to keep track of all the changes that have been applied to the database.
 /// 3. Creates a `deploy_log_config` table to store configuration settings related to the deployment process.
 /// 4. Creates a `deploy_execution` table to record each deployment execution.
-/// 5. Inserts the initial configuration settings into the `deploy_log_config` table.
+/// 5. Inserts the initial configuration settings into the `deploy_log_config` table, including the
+///    `metadata_version` of the tables.
+///
+/// Running it again on an initialized database keeps the recorded settings. The tables created by
+/// an older version of oxigration are not upgraded here but by `upgrade_deploy_log`, which has to
+/// run under the deployment lock.
 ///
 /// The `deploy_log` table is crucial for tracking which changes have been applied to the database, ensuring that
 /// changes are not reapplied, and enabling rollback functionality. The `deploy_log_config` table stores settings
@@ -24,6 +35,9 @@ use crate::utils::{format_query_with_schema, SCHEMA_SUPPORT};
 /// # Arguments
 ///
 /// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `environment_name` - The name of the environment the database belongs to, recorded as the
+///   `env` setting and checked before every migration.
 ///
 /// # Returns
 ///
@@ -36,129 +50,786 @@ use crate::utils::{format_query_with_schema, SCHEMA_SUPPORT};
 /// This function will return an error if:
 /// * There is an issue connecting to the database.
 /// * There is an error executing the SQL statements to create the schema, tables, or insert the configuration settings.
-pub async fn init_deploy_log(connection_string: &str) -> Result<bool, Box<dyn Error>> {
+/// * The database is already initialized for another environment.
+pub async fn init_deploy_log(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    environment_name: &str,
+) -> Result<bool, Box<dyn Error>> {
     let pool = AnyPool::connect(connection_string).await?;
 
-    // Check if the database is SQLite
-    let is_sqlite = connection_string.starts_with("sqlite");
-
-    if !is_sqlite {
-        // Check if the database supports schemas
-        let supports_schemas: bool = query_scalar(
-            "SELECT EXISTS (SELECT 1 FROM information_schema.schemata WHERE schema_name = 'information_schema');"
-        )
-        .fetch_one(&pool)
+    if metadata.supports_schemas {
+        // Create the metadata schema if it does not exist
+        pool.execute(&*format!(
+            "CREATE SCHEMA IF NOT EXISTS {};",
+            metadata.schema
+        ))
         .await?;
+    }
 
-        SCHEMA_SUPPORT.store(supports_schemas, Ordering::Relaxed);
+    // Create the deploy_log, deploy_log_config and deploy_execution tables if they do not exist
+    for table in metadata_tables(metadata.kind) {
+        pool.execute(&*format_query_with_schema(&table, metadata))
+            .await?;
+    }
 
-        if supports_schemas {
-            // Create oxigration schema if it does not exist
-            pool.execute("CREATE SCHEMA IF NOT EXISTS oxigration;")
+    // Running init again keeps the settings of the first run and only upgrades the tables, unless
+    // the database belongs to another environment
+    if let Some(initialized_env) = read_config_value(connection_string, metadata, "env").await? {
+        pool.close().await;
+        if initialized_env != environment_name {
+            return Err(format!(
+                "The target database is already initialized for the environment '{}'",
+                initialized_env
+            )
+            .into());
+        }
+        return Ok(true);
+    }
+
+    // Insert the initial configuration settings into the deploy_log_config table
+    sqlx::query(&format_query_with_schema(
+        "INSERT INTO {schema_prefix}deploy_log_config (\"key\", value) VALUES
+            ('init_version', $1),
+            ('init_at', CAST(CURRENT_TIMESTAMP AS {text})),
+            ('last_version', $2),
+            ('last_applied_at', CAST(CURRENT_TIMESTAMP AS {text})),
+            ('schema', $3),
+            ('env', $4),
+            ('db_type', $5),
+            ('metadata_version', $6);",
+        metadata,
+    ))
+    .bind(env!("CARGO_PKG_VERSION"))
+    .bind(env!("CARGO_PKG_VERSION"))
+    .bind(&metadata.schema)
+    .bind(environment_name)
+    .bind(metadata.kind.to_string())
+    .bind(METADATA_VERSION.to_string())
+    .execute(&pool)
+    .await?;
+
+    pool.close().await;
+    Ok(true)
+}
+
+/// Upgrades the deploy log tables created by an older version of oxigration.
+///
+/// The layout of the deploy log tables is versioned by the `metadata_version` setting, which is
+/// `1` for the tables created before it was recorded. When the `last_version` setting differs from
+/// the running version of oxigration, the upgrades of `metadata_upgrades` newer than the metadata
+/// version of the database are applied in version order, each in its own transaction with the
+/// update of `metadata_version`, then `last_version` is updated. MySQL commits every DDL statement,
+/// so a failed upgrade keeps the versions applied before it and is resumed from the failed one.
+///
+/// The caller must hold the deployment lock, so that concurrent runs do not upgrade the tables
+/// twice.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(true)` if the settings or the tables were upgraded.
+/// * `Ok(false)` if the deploy log was already up to date.
+/// * `Err(Box<dyn Error>)` if the deploy log cannot be upgraded.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * There is an issue connecting to the database or reading the settings.
+/// * The metadata version of the database is newer than the one of the running oxigration, which
+///   must then be upgraded.
+/// * An upgrade statement fails, in which case the versions before it stay upgraded.
+pub async fn upgrade_deploy_log(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<bool, Box<dyn Error>> {
+    if !deploy_log_outdated(connection_string, metadata).await? {
+        return Ok(false);
+    }
+    let metadata_version = read_metadata_version(connection_string, metadata).await?;
+
+    let pool = AnyPool::connect(connection_string).await?;
+    for (version, statements) in metadata_upgrades(metadata.kind) {
+        if version <= metadata_version {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        for statement in statements {
+            tx.execute(&*format_query_with_schema(statement, metadata))
                 .await?;
         }
+        set_config_value(&mut tx, metadata, "metadata_version", &version.to_string()).await?;
+        tx.commit().await?;
+        log::info!("Deploy log upgraded to metadata version {}", version);
     }
 
-    // Create deploy_log table if it does not exist
-    pool.execute(
-        &*format_query_with_schema(
-            "CREATE TABLE IF NOT EXISTS {schema_prefix}deploy_log (
-                id INTEGER PRIMARY KEY,
+    let mut tx = pool.begin().await?;
+    set_config_value(
+        &mut tx,
+        metadata,
+        "metadata_version",
+        &METADATA_VERSION.to_string(),
+    )
+    .await?;
+    set_config_value(&mut tx, metadata, "last_version", env!("CARGO_PKG_VERSION")).await?;
+    tx.commit().await?;
+
+    pool.close().await;
+    Ok(true)
+}
+
+/// Returns whether the deploy log has to be upgraded with `upgrade_deploy_log`, because its tables
+/// or its `last_version` setting were written by another version of oxigration.
+///
+/// # Errors
+///
+/// This function returns an error if the settings cannot be read, or if the metadata version of
+/// the database is newer than the one of the running oxigration.
+pub async fn deploy_log_outdated(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<bool, Box<dyn Error>> {
+    let metadata_version = read_metadata_version(connection_string, metadata).await?;
+    let last_version = read_config_value(connection_string, metadata, "last_version").await?;
+    Ok(metadata_version < METADATA_VERSION
+        || last_version.as_deref() != Some(env!("CARGO_PKG_VERSION")))
+}
+
+/// Checks that the deploy log tables have the layout of the running version of oxigration, for
+/// the commands that read them without upgrading them.
+///
+/// # Errors
+///
+/// This function returns an error if the settings cannot be read, or if the metadata version of
+/// the database is older or newer than the one of the running oxigration.
+pub async fn check_metadata_version(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<(), Box<dyn Error>> {
+    let metadata_version = read_metadata_version(connection_string, metadata).await?;
+    if metadata_version < METADATA_VERSION {
+        return Err(format!(
+            "The deploy log has metadata version {}, but oxigration {} needs version {}, run 'oxigration migrate' or 'oxigration init' to upgrade it",
+            metadata_version,
+            env!("CARGO_PKG_VERSION"),
+            METADATA_VERSION
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads the `metadata_version` setting, `1` if it is not recorded.
+///
+/// # Errors
+///
+/// This function returns an error if the setting cannot be read, or if it is newer than the
+/// metadata version of the running oxigration.
+async fn read_metadata_version(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<i64, Box<dyn Error>> {
+    let metadata_version =
+        match read_config_value(connection_string, metadata, "metadata_version").await? {
+            Some(version) => version.parse::<i64>()?,
+            None => 1,
+        };
+    if metadata_version > METADATA_VERSION {
+        return Err(format!(
+            "The deploy log has metadata version {}, but oxigration {} supports up to version {}, upgrade oxigration",
+            metadata_version,
+            env!("CARGO_PKG_VERSION"),
+            METADATA_VERSION
+        )
+        .into());
+    }
+    Ok(metadata_version)
+}
+
+/// Returns the statements upgrading the deploy log tables to each metadata version, in version
+/// order. A new version is added whenever a release changes the layout of the tables, and its
+/// statements are templates for `format_query_with_schema`. MySQL commits each DDL statement, so
+/// the statements of a version must succeed when they run again after a failure.
+fn metadata_upgrades(kind: DatabaseKind) -> Vec<(i64, Vec<&'static str>)> {
+    // Version 2: the ids are generated by the database instead of MAX(id) + 1. SQLite already
+    // generates the `INTEGER PRIMARY KEY` ids.
+    let generated_ids = match kind {
+        DatabaseKind::PostgreSql => vec![
+            "ALTER TABLE {schema_prefix}deploy_log ALTER COLUMN id TYPE BIGINT;",
+            "ALTER TABLE {schema_prefix}deploy_log ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;",
+            "SELECT setval(pg_get_serial_sequence('{schema_prefix}deploy_log', 'id'), COALESCE(MAX(id), 0) + 1, false)
+                FROM {schema_prefix}deploy_log;",
+            "ALTER TABLE {schema_prefix}deploy_execution ALTER COLUMN id TYPE BIGINT;",
+            "ALTER TABLE {schema_prefix}deploy_execution ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;",
+            "SELECT setval(pg_get_serial_sequence('{schema_prefix}deploy_execution', 'id'), COALESCE(MAX(id), 0) + 1, false)
+                FROM {schema_prefix}deploy_execution;",
+        ],
+        DatabaseKind::MySql => vec![
+            "ALTER TABLE {schema_prefix}deploy_log MODIFY id BIGINT AUTO_INCREMENT;",
+            "ALTER TABLE {schema_prefix}deploy_execution MODIFY id BIGINT AUTO_INCREMENT;",
+        ],
+        DatabaseKind::Sqlite => vec![],
+    };
+
+    // Version 3: the physical schema each change was applied to is recorded
+    let physical_schema =
+        vec!["ALTER TABLE {schema_prefix}deploy_log ADD COLUMN physical_schema TEXT;"];
+
+    vec![(2, generated_ids), (3, physical_schema)]
+}
+
+/// Inserts or replaces a setting of the `deploy_log_config` table.
+async fn set_config_value(
+    tx: &mut Transaction<'_, Any>,
+    metadata: &MetadataContext,
+    key: &str,
+    value: &str,
+) -> Result<(), Box<dyn Error>> {
+    let updated = query(&format_query_with_schema(
+        "UPDATE {schema_prefix}deploy_log_config SET value = $1 WHERE \"key\" = $2;",
+        metadata,
+    ))
+    .bind(value)
+    .bind(key)
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        query(&format_query_with_schema(
+            "INSERT INTO {schema_prefix}deploy_log_config (\"key\", value) VALUES ($1, $2);",
+            metadata,
+        ))
+        .bind(key)
+        .bind(value)
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns the statements creating the deploy log tables on a kind of database.
+///
+/// The ids of the `deploy_log` and `deploy_execution` tables are generated by the database, with
+/// an identity column in PostgreSQL and an auto-increment column in MySQL and SQLite, so that
+/// concurrent deployments never compute the same id. MySQL cannot index `TEXT` columns, so the
+/// keys of the `deploy_log_config` table are `VARCHAR` there. The statements are templates for
+/// `format_query_with_schema`.
+fn metadata_tables(kind: DatabaseKind) -> Vec<String> {
+    let (id_column, key_type) = match kind {
+        DatabaseKind::PostgreSql => (
+            "BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY",
+            "TEXT",
+        ),
+        DatabaseKind::MySql => ("BIGINT AUTO_INCREMENT PRIMARY KEY", "VARCHAR(255)"),
+        DatabaseKind::Sqlite => ("INTEGER PRIMARY KEY AUTOINCREMENT", "TEXT"),
+    };
+
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {{schema_prefix}}deploy_log (
+                id {},
                 change_name TEXT NOT NULL,
                 object_name TEXT NOT NULL,
                 change_type TEXT NOT NULL,
                 content_hash TEXT,
                 applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 rollback_content TEXT,
-                deploy_execution_id INTEGER
+                deploy_execution_id BIGINT,
+                physical_schema TEXT
             );",
-        )
-        .to_string(),
-    )
-    .await?;
-
-    // Create deploy_log_config table if it does not exist
-    pool.execute(
-        &*format_query_with_schema(
-            "CREATE TABLE IF NOT EXISTS {schema_prefix}deploy_log_config (
-                key TEXT PRIMARY KEY,
+            id_column
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {{schema_prefix}}deploy_log_config (
+                \"key\" {} PRIMARY KEY,
                 value TEXT NOT NULL
             );",
-        )
-        .to_string(),
-    )
-    .await?;
-
-    // Create deploy_execution table if it does not exist
-    pool.execute(
-        &*format_query_with_schema(
-            "CREATE TABLE IF NOT EXISTS {schema_prefix}deploy_execution (
-                id INTEGER PRIMARY KEY,
+            key_type
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {{schema_prefix}}deploy_execution (
+                id {},
                 requester TEXT NOT NULL,
                 executor TEXT NOT NULL,
-                schema TEXT NOT NULL,
+                \"schema\" TEXT NOT NULL,
                 product_version TEXT NOT NULL,
                 time_started TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                time_completed TIMESTAMP,
+                time_completed TIMESTAMP NULL,
                 status TEXT NOT NULL,
                 reason TEXT
             );",
+            id_column
+        ),
+    ]
+}
+
+/// Detects the metadata context of the target database: its kind, whether it supports schemas,
+/// and the schema of its deploy log tables, so that `format_query_with_schema` resolves the deploy
+/// log tables in the right place with the right syntax.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool to the target database.
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata_schema` - The schema of the deploy log tables of the environment.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(MetadataContext)` with the metadata context of the target database.
+/// * `Err(Box<dyn Error>)` if the scheme of the connection string is not supported or the check
+///   cannot be executed.
+pub async fn metadata_context(
+    pool: &AnyPool,
+    connection_string: &str,
+    metadata_schema: &str,
+) -> Result<MetadataContext, Box<dyn Error>> {
+    let kind = DatabaseKind::from_connection_string(connection_string)?;
+    // SQLite has no schemas nor information_schema
+    let supports_schemas = if kind == DatabaseKind::Sqlite {
+        false
+    } else {
+        let schemas: i64 = query_scalar(
+            "SELECT COUNT(*) FROM information_schema.schemata WHERE schema_name = 'information_schema';"
         )
-        .to_string(),
-    )
-    .await?;
+        .fetch_one(pool)
+        .await?;
+        schemas > 0
+    };
 
-    // Insert the initial configuration settings into the deploy_log_config table
-    sqlx::query(
-        &*format_query_with_schema(
-            "INSERT INTO {schema_prefix}deploy_log_config (key, value) VALUES 
-                            ('init_version', $1),
-                            ('init_at', now()),
-                            ('last_version', $2),
-                            ('last_applied_at', now()),
-                            ('schema', $3),
-                            ('env', $4),
-                            ('db_type', $5);",
-        )
-        .to_string(),
-    )
-    .bind(env!("CARGO_PKG_VERSION"))
-    .bind(env!("CARGO_PKG_VERSION"))
-    .bind("oxigration")
-    .bind(env::var("ENV").unwrap_or_else(|_| "DEV".to_string()))
-    .bind("postgresql")
-    .execute(&pool)
-    .await?;
+    Ok(MetadataContext {
+        kind,
+        schema: metadata_schema.to_string(),
+        supports_schemas,
+    })
+}
 
-    Ok(true)
+/// Checks whether the `deploy_log` table exists in the metadata schema of the target database.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(bool)` with `true` if the table exists.
+/// * `Err(Box<dyn Error>)` if the catalog cannot be queried.
+pub async fn deploy_log_exists(
+    pool: &AnyPool,
+    metadata: &MetadataContext,
+) -> Result<bool, Box<dyn Error>> {
+    let tables: i64 = if metadata.supports_schemas {
+        query_scalar(&format_query_with_schema(
+            "SELECT COUNT(*) FROM information_schema.tables
+                WHERE table_schema = $1 AND table_name = 'deploy_log';",
+            metadata,
+        ))
+        .bind(&metadata.schema)
+        .fetch_one(pool)
+        .await?
+    } else {
+        query_scalar(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'deploy_log';",
+        )
+        .fetch_one(pool)
+        .await?
+    };
+    Ok(tables > 0)
 }
 
-/// The function reads the deploy log from the database
-/// Returns an indexmap of DatabaseObject
+/// Reads the deploy log from the target database.
+///
+/// Every row of the `deploy_log` table is returned as a `DatabaseObject`, keyed by the same
+/// `schema.type.object.change` key that `read_source_code` produces for the change, so both can be
+/// compared to compute the changeset. The content of the change is not stored in the deploy log,
+/// so the returned objects only carry its hash.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(IndexMap<String, DatabaseObject>)` with the deployed changes, in deploy order.
+/// * `Err(Box<dyn Error>)` if the deploy log cannot be read.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * There is an issue connecting to the database.
+/// * The deploy log table does not exist.
+/// * A row of the deploy log has an object name that is not qualified with its schema.
 pub async fn read_deploy_log(
     connection_string: &str,
+    metadata: &MetadataContext,
 ) -> Result<IndexMap<String, DatabaseObject>, Box<dyn Error>> {
     let pool = AnyPool::connect(connection_string).await?;
-    let deploy_log = IndexMap::new();
+    let mut deploy_log = IndexMap::new();
 
-    let rows = query("SELECT change_name FROM oxigration.deploy_log;")
-        .fetch_all(&pool)
-        .await?;
+    let rows = query(&format_query_with_schema(
+        "SELECT change_name, object_name, change_type, content_hash,
+                CAST(applied_at AS {text}) AS applied_at, rollback_content, deploy_execution_id,
+                physical_schema
+            FROM {schema_prefix}deploy_log
+            ORDER BY id;",
+        metadata,
+    ))
+    .fetch_all(&pool)
+    .await?;
+
+    for row in rows {
+        let change_name: String = row.try_get("change_name")?;
+        let qualified_object_name: String = row.try_get("object_name")?;
+        let object_type: String = row.try_get("change_type")?;
 
-    for _ in rows {
-        // let change_name: String = row.try_get("change_name")?;
-        // Assuming DatabaseObject can be created from change_name
-        // let db_object = DatabaseObject::new(change_name.clone(), /* other required args */);
-        // deploy_log.insert(change_name, db_object);
+        let (schema_name, object_name) =
+            qualified_object_name.split_once('.').ok_or_else(|| {
+                format!(
+                    "Object name '{}' in deploy log is not qualified with its schema",
+                    qualified_object_name
+                )
+            })?;
+        let key = format!(
+            "{}.{}.{}.{}",
+            schema_name, object_type, object_name, change_name
+        );
+
+        let mut db_object = DatabaseObject::new(
+            key.clone(),
+            String::new(),
+            HashSet::new(),
+            HashMap::new(),
+            None,
+        );
+        db_object.content_hash = row
+            .try_get::<Option<String>, _>("content_hash")?
+            .unwrap_or_default();
+        db_object.schema_name = schema_name.to_string();
+        db_object.object_type = object_type;
+        db_object.object_name = object_name.to_string();
+        db_object.applied_at = row.try_get("applied_at")?;
+        db_object.rollback_content = row.try_get("rollback_content")?;
+        db_object.deploy_execution_id = row.try_get("deploy_execution_id")?;
+        db_object.physical_schema = row.try_get("physical_schema")?;
+
+        deploy_log.insert(key, db_object);
     }
 
+    pool.close().await;
     Ok(deploy_log)
 }
 
+/// A row of the `deploy_execution` table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeployExecution {
+    /// The id of the execution, referenced by the `deploy_log` rows it applied.
+    pub id: i64,
+    /// The person or system that requested the deployment.
+    pub requester: String,
+    /// The user and host that executed the deployment.
+    pub executor: String,
+    /// The schema the deployment was executed against.
+    pub schema: String,
+    /// The version of oxigration that executed the deployment.
+    pub product_version: String,
+    /// When the execution started.
+    pub time_started: Option<String>,
+    /// When the execution completed, `None` while it is running.
+    pub time_completed: Option<String>,
+    /// The status of the execution.
+    pub status: String,
+    /// The reason of the status, such as the error that made the execution fail.
+    pub reason: Option<String>,
+}
+
+/// The status of a row of the `deploy_execution` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeployExecutionStatus {
+    /// The deployment is in progress.
+    Running,
+    /// Every change of the deployment was applied.
+    Succeeded,
+    /// The deployment stopped on an error, recorded in the `reason` column.
+    Failed,
+}
+
+impl fmt::Display for DeployExecutionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            DeployExecutionStatus::Running => "RUNNING",
+            DeployExecutionStatus::Succeeded => "SUCCEEDED",
+            DeployExecutionStatus::Failed => "FAILED",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Opens a row in the `deploy_execution` table with the `RUNNING` status.
+///
+/// The id of the row is stored in the `deploy_execution_id` column of every `deploy_log` row
+/// applied by the deployment, and the row is closed by `finish_deploy_execution`.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `requester` - The person or system that requested the deployment.
+/// * `executor` - The user and host that executes the deployment.
+/// * `schema` - The schemas the deployment is executed against.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(i64)` with the id of the new execution.
+/// * `Err(Box<dyn Error>)` if the row cannot be inserted.
+pub async fn start_deploy_execution(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    requester: &str,
+    executor: &str,
+    schema: &str,
+) -> Result<i64, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    // The id is generated by the database, PostgreSQL and SQLite return it and the driver of
+    // MySQL reports it
+    let returns_id = metadata.kind != DatabaseKind::MySql;
+    let insert = format_query_with_schema(
+        &format!(
+            "INSERT INTO {{schema_prefix}}deploy_execution
+                (requester, executor, \"schema\", product_version, status)
+                VALUES ($1, $2, $3, $4, $5){};",
+            if returns_id { " RETURNING id" } else { "" }
+        ),
+        metadata,
+    );
+    let insert = query(&insert)
+        .bind(requester)
+        .bind(executor)
+        .bind(schema)
+        .bind(env!("CARGO_PKG_VERSION"))
+        .bind(DeployExecutionStatus::Running.to_string());
+
+    let id: i64 = if returns_id {
+        insert.fetch_one(&pool).await?.try_get("id")?
+    } else {
+        insert
+            .execute(&pool)
+            .await?
+            .last_insert_id()
+            .ok_or("The database did not report the id of the deploy execution")?
+    };
+
+    pool.close().await;
+    Ok(id)
+}
+
+/// Closes a row of the `deploy_execution` table with its final status.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `id` - The id of the execution returned by `start_deploy_execution`.
+/// * `status` - The final status of the execution.
+/// * `reason` - The reason of the status, such as the error that made the deployment fail.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(())` if the row is updated.
+/// * `Err(Box<dyn Error>)` if the row cannot be updated.
+pub async fn finish_deploy_execution(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    id: i64,
+    status: DeployExecutionStatus,
+    reason: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    query(&format_query_with_schema(
+        "UPDATE {schema_prefix}deploy_execution
+            SET status = $1, reason = $2, time_completed = CURRENT_TIMESTAMP
+            WHERE id = $3;",
+        metadata,
+    ))
+    .bind(status.to_string())
+    .bind(reason)
+    .bind(id)
+    .execute(&pool)
+    .await?;
+
+    pool.close().await;
+    Ok(())
+}
+
+/// Reads the last row of the `deploy_execution` table.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Some(DeployExecution))` with the execution with the highest id.
+/// * `Ok(None)` if no deployment was executed yet.
+/// * `Err(Box<dyn Error>)` if the table cannot be read.
+pub async fn read_last_deploy_execution(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<Option<DeployExecution>, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    let row = query(&format_query_with_schema(
+        "SELECT id, requester, executor, \"schema\", product_version,
+                CAST(time_started AS {text}) AS time_started,
+                CAST(time_completed AS {text}) AS time_completed, status, reason
+            FROM {schema_prefix}deploy_execution
+            ORDER BY id DESC
+            LIMIT 1;",
+        metadata,
+    ))
+    .fetch_optional(&pool)
+    .await?;
+
+    let execution = row.as_ref().map(deploy_execution_from_row).transpose()?;
+
+    pool.close().await;
+    Ok(execution)
+}
+
+/// Reads a row of the `deploy_execution` table.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `id` - The id of the execution.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Some(DeployExecution))` with the execution.
+/// * `Ok(None)` if there is no execution with this id.
+/// * `Err(Box<dyn Error>)` if the table cannot be read.
+pub async fn read_deploy_execution(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    id: i64,
+) -> Result<Option<DeployExecution>, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    let row = query(&format_query_with_schema(
+        "SELECT id, requester, executor, \"schema\", product_version,
+                CAST(time_started AS {text}) AS time_started,
+                CAST(time_completed AS {text}) AS time_completed, status, reason
+            FROM {schema_prefix}deploy_execution
+            WHERE id = $1;",
+        metadata,
+    ))
+    .bind(id)
+    .fetch_optional(&pool)
+    .await?;
+    let execution = row.as_ref().map(deploy_execution_from_row).transpose()?;
+
+    pool.close().await;
+    Ok(execution)
+}
+
+/// Builds a `DeployExecution` from a row of the `deploy_execution` table.
+fn deploy_execution_from_row(row: &AnyRow) -> Result<DeployExecution, sqlx::Error> {
+    Ok(DeployExecution {
+        id: row.try_get("id")?,
+        requester: row.try_get("requester")?,
+        executor: row.try_get("executor")?,
+        schema: row.try_get("schema")?,
+        product_version: row.try_get("product_version")?,
+        time_started: row.try_get("time_started")?,
+        time_completed: row.try_get("time_completed")?,
+        status: row.try_get("status")?,
+        reason: row.try_get("reason")?,
+    })
+}
+
+/// Reads a configuration setting from the `deploy_log_config` table.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `key` - The key of the setting, such as `last_version`.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Some(String))` with the value of the setting.
+/// * `Ok(None)` if the setting does not exist.
+/// * `Err(Box<dyn Error>)` if the table cannot be read.
+pub async fn read_config_value(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    key: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    let value = query_scalar(&format_query_with_schema(
+        "SELECT value FROM {schema_prefix}deploy_log_config WHERE \"key\" = $1;",
+        metadata,
+    ))
+    .bind(key)
+    .fetch_optional(&pool)
+    .await?;
+
+    pool.close().await;
+    Ok(value)
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::utils::DEFAULT_METADATA_SCHEMA;
     use sqlx::AnyPool;
+    use tempfile::TempDir;
+
+    /// Returns a connection string to an empty SQLite database in a temporary directory, which is
+    /// deleted when the returned `TempDir` is dropped, and the metadata context of that database.
+    pub(crate) async fn sqlite_database(
+    ) -> Result<(TempDir, String, MetadataContext), Box<dyn Error>> {
+        sqlx::any::install_default_drivers();
+        let dir = tempfile::tempdir()?;
+        let connection_string = format!(
+            "sqlite://{}?mode=rwc",
+            dir.path().join("deploy.db").display()
+        );
+        let metadata = MetadataContext {
+            kind: DatabaseKind::Sqlite,
+            schema: DEFAULT_METADATA_SCHEMA.to_string(),
+            supports_schemas: false,
+        };
+        Ok((dir, connection_string, metadata))
+    }
+
+    /// Returns a SQLite database like `sqlite_database`, with the deploy log initialized for the
+    /// `TEST` environment.
+    pub(crate) async fn initialized_sqlite_database(
+    ) -> Result<(TempDir, String, MetadataContext), Box<dyn Error>> {
+        let (dir, connection_string, metadata) = sqlite_database().await?;
+        init_deploy_log(&connection_string, &metadata, "TEST").await?;
+        Ok((dir, connection_string, metadata))
+    }
 
     #[tokio::test]
     async fn test_init_deploy_log() -> Result<(), Box<dyn Error>> {
@@ -168,9 +839,10 @@ mod tests {
         // Use an in-memory SQLite database for testing
         let connection_string = "postgresql://postgres@0.0.0.0/postgres";
         let pool = AnyPool::connect(connection_string).await?;
+        let metadata = metadata_context(&pool, connection_string, DEFAULT_METADATA_SCHEMA).await?;
 
         // Initialize the deploy log
-        let result = init_deploy_log(connection_string).await?;
+        let result = init_deploy_log(connection_string, &metadata, "DEV").await?;
         assert!(result, "Initialization should return true");
 
         // Verify the oxigration schema exists (only if not SQLite)
@@ -212,4 +884,312 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_init_deploy_log_on_sqlite() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+
+        assert!(init_deploy_log(&connection_string, &metadata, "TEST").await?);
+
+        let pool = AnyPool::connect(&connection_string).await?;
+        assert!(deploy_log_exists(&pool, &metadata).await?);
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "db_type").await?,
+            Some("sqlite".to_string())
+        );
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "env").await?,
+            Some("TEST".to_string())
+        );
+        assert!(read_config_value(&connection_string, &metadata, "init_at")
+            .await?
+            .is_some_and(|init_at| !init_at.is_empty()));
+
+        // Ids are generated by the database
+        let first =
+            start_deploy_execution(&connection_string, &metadata, "alice", "ci@build", "main")
+                .await?;
+        let second =
+            start_deploy_execution(&connection_string, &metadata, "bob", "ci@build", "main")
+                .await?;
+        assert_eq!((first, second), (1, 2));
+        pool.execute(
+            "INSERT INTO deploy_log (change_name, object_name, change_type) VALUES ('change0', 'main.t1', 'table');",
+        )
+        .await?;
+        assert_eq!(
+            read_deploy_log(&connection_string, &metadata).await?.len(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_init_deploy_log_is_idempotent() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+
+        assert!(init_deploy_log(&connection_string, &metadata, "TEST").await?);
+        let init_at = read_config_value(&connection_string, &metadata, "init_at").await?;
+        assert!(init_deploy_log(&connection_string, &metadata, "TEST").await?);
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "init_at").await?,
+            init_at
+        );
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "metadata_version").await?,
+            Some(METADATA_VERSION.to_string())
+        );
+
+        let error = init_deploy_log(&connection_string, &metadata, "PROD")
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("already initialized for the environment 'TEST'"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_deploy_log_of_older_version() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+        init_deploy_log(&connection_string, &metadata, "TEST").await?;
+        assert!(!upgrade_deploy_log(&connection_string, &metadata).await?);
+
+        // A deploy log initialized by a version without metadata versioning nor physical schemas
+        let pool = AnyPool::connect(&connection_string).await?;
+        pool.execute(
+            "DELETE FROM deploy_log_config WHERE key = 'metadata_version';
+            UPDATE deploy_log_config SET value = '0.0.1' WHERE key = 'last_version';
+            ALTER TABLE deploy_log DROP COLUMN physical_schema;",
+        )
+        .await?;
+
+        let error = check_metadata_version(&connection_string, &metadata)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("has metadata version 1"));
+        assert!(deploy_log_outdated(&connection_string, &metadata).await?);
+
+        assert!(upgrade_deploy_log(&connection_string, &metadata).await?);
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "metadata_version").await?,
+            Some(METADATA_VERSION.to_string())
+        );
+        check_metadata_version(&connection_string, &metadata).await?;
+        let columns: i64 = query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('deploy_log') WHERE name = 'physical_schema';",
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(columns, 1);
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "last_version").await?,
+            Some(env!("CARGO_PKG_VERSION").to_string())
+        );
+        assert!(!upgrade_deploy_log(&connection_string, &metadata).await?);
+
+        // A failed version keeps the versions upgraded before it
+        pool.execute(
+            "DELETE FROM deploy_log_config WHERE key = 'metadata_version';
+            UPDATE deploy_log_config SET value = '0.0.1' WHERE key = 'last_version';",
+        )
+        .await?;
+        assert!(upgrade_deploy_log(&connection_string, &metadata)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("duplicate column"));
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "metadata_version").await?,
+            Some("2".to_string())
+        );
+        pool.execute("ALTER TABLE deploy_log DROP COLUMN physical_schema;")
+            .await?;
+        assert!(upgrade_deploy_log(&connection_string, &metadata).await?);
+        check_metadata_version(&connection_string, &metadata).await?;
+
+        // A deploy log upgraded by a newer version
+        pool.execute("UPDATE deploy_log_config SET value = '99' WHERE key = 'metadata_version';")
+            .await?;
+        let error = upgrade_deploy_log(&connection_string, &metadata)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("upgrade oxigration"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_deploy_log_rebuilds_source_code_keys() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+        let pool = AnyPool::connect(&connection_string).await?;
+        pool.execute(
+            "CREATE TABLE deploy_log (
+                id INTEGER PRIMARY KEY,
+                change_name TEXT NOT NULL,
+                object_name TEXT NOT NULL,
+                change_type TEXT NOT NULL,
+                content_hash TEXT,
+                applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                rollback_content TEXT,
+                deploy_execution_id INTEGER,
+                physical_schema TEXT
+            );
+            INSERT INTO deploy_log (id, change_name, object_name, change_type, content_hash, rollback_content, deploy_execution_id, physical_schema)
+                VALUES (1, 'change0', 'baseline.table_a', 'table', 'abc', 'DROP TABLE table_a;', 3, 'app_dev'),
+                       (2, 'root0', 'baseline.view1', 'view', NULL, NULL, NULL, NULL);",
+        )
+        .await?;
+
+        let deploy_log = read_deploy_log(&connection_string, &metadata).await?;
+        let keys: Vec<&String> = deploy_log.keys().collect();
+        assert_eq!(
+            keys,
+            vec![
+                "baseline.table.table_a.change0",
+                "baseline.view.view1.root0"
+            ]
+        );
+
+        let change = &deploy_log["baseline.table.table_a.change0"];
+        assert_eq!(change.schema_name, "baseline");
+        assert_eq!(change.object_type, "table");
+        assert_eq!(change.object_name, "table_a");
+        assert_eq!(change.short_change_name(), "change0");
+        assert_eq!(change.content_hash, "abc");
+        assert_eq!(
+            change.rollback_content.as_deref(),
+            Some("DROP TABLE table_a;")
+        );
+        assert_eq!(change.deploy_execution_id, Some(3));
+        assert_eq!(change.physical_schema.as_deref(), Some("app_dev"));
+        assert!(change.applied_at.is_some());
+
+        let view = &deploy_log["baseline.view.view1.root0"];
+        assert_eq!(view.content_hash, "");
+        assert_eq!(view.deploy_execution_id, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_last_deploy_execution_and_config() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+        let pool = AnyPool::connect(&connection_string).await?;
+        pool.execute(
+            "CREATE TABLE deploy_log_config (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+            CREATE TABLE deploy_execution (
+                id INTEGER PRIMARY KEY,
+                requester TEXT NOT NULL,
+                executor TEXT NOT NULL,
+                schema TEXT NOT NULL,
+                product_version TEXT NOT NULL,
+                time_started TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                time_completed TIMESTAMP,
+                status TEXT NOT NULL,
+                reason TEXT
+            );",
+        )
+        .await?;
+
+        assert_eq!(
+            read_last_deploy_execution(&connection_string, &metadata).await?,
+            None
+        );
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "last_version").await?,
+            None
+        );
+
+        pool.execute(
+            "INSERT INTO deploy_log_config (key, value) VALUES ('last_version', '0.1.0');
+            INSERT INTO deploy_execution (id, requester, executor, schema, product_version, status)
+                VALUES (1, 'alice', 'ci@build', 'oxigration', '0.1.0', 'SUCCEEDED'),
+                       (2, 'bob', 'ci@build', 'oxigration', '0.1.0', 'FAILED');",
+        )
+        .await?;
+
+        let execution = read_last_deploy_execution(&connection_string, &metadata)
+            .await?
+            .unwrap();
+        assert_eq!(execution.id, 2);
+        assert_eq!(execution.requester, "bob");
+        assert_eq!(execution.status, "FAILED");
+        assert!(execution.time_started.is_some());
+        assert_eq!(execution.time_completed, None);
+        assert_eq!(
+            read_config_value(&connection_string, &metadata, "last_version").await?,
+            Some("0.1.0".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deploy_execution_lifecycle() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = sqlite_database().await?;
+        let pool = AnyPool::connect(&connection_string).await?;
+        pool.execute(
+            "CREATE TABLE deploy_execution (
+                id INTEGER PRIMARY KEY,
+                requester TEXT NOT NULL,
+                executor TEXT NOT NULL,
+                schema TEXT NOT NULL,
+                product_version TEXT NOT NULL,
+                time_started TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                time_completed TIMESTAMP,
+                status TEXT NOT NULL,
+                reason TEXT
+            );",
+        )
+        .await?;
+
+        let first = start_deploy_execution(
+            &connection_string,
+            &metadata,
+            "alice",
+            "ci@build",
+            "baseline",
+        )
+        .await?;
+        finish_deploy_execution(
+            &connection_string,
+            &metadata,
+            first,
+            DeployExecutionStatus::Succeeded,
+            None,
+        )
+        .await?;
+
+        let second =
+            start_deploy_execution(&connection_string, &metadata, "bob", "ci@build", "baseline")
+                .await?;
+        assert_eq!(second, first + 1);
+        let running = read_last_deploy_execution(&connection_string, &metadata)
+            .await?
+            .unwrap();
+        assert_eq!(running.status, "RUNNING");
+        assert_eq!(running.product_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(running.time_completed, None);
+
+        finish_deploy_execution(
+            &connection_string,
+            &metadata,
+            second,
+            DeployExecutionStatus::Failed,
+            Some("Failed to apply change"),
+        )
+        .await?;
+        let failed = read_last_deploy_execution(&connection_string, &metadata)
+            .await?
+            .unwrap();
+        assert_eq!(failed.id, second);
+        assert_eq!(failed.status, "FAILED");
+        assert_eq!(failed.reason.as_deref(), Some("Failed to apply change"));
+        assert!(failed.time_completed.is_some());
+
+        Ok(())
+    }
 }
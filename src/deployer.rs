@@ -0,0 +1,788 @@
+use indexmap::IndexMap;
+use sqlx::{Any, AnyPool, Executor, Transaction};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::changeset::{Change, ChangeAction, ChangeSet};
+use crate::source_code::DatabaseObject;
+use crate::utils::{
+    format_query_with_schema, map_schema_names, DatabaseKind, MetadataContext, SqlDialect,
+};
+
+/// Applies the pending changes of a changeset to the target database.
+///
+/// Each change is applied in its own transaction, together with the matching update of the
+/// deploy log:
+///
+/// * `Deploy` executes the change and inserts its row in the deploy log, with its rollback.
+/// * `Redeploy` drops the object, executes the change and replaces its row in the deploy log,
+///   without rollback: it would drop the object instead of restoring its previous definition,
+///   which is not kept. The same goes for the changes recreated for a redeployed change.
+/// * `Drop` drops the object and deletes its row from the deploy log.
+///
+/// If a change fails, its transaction is rolled back and the deployment stops, so in PostgreSQL
+/// and SQLite the deploy log always reflects the changes that were applied to the database.
+///
+/// MySQL commits every DDL statement (`CREATE`, `ALTER`, `DROP`, ...) as soon as it is executed,
+/// and a transaction cannot roll it back. When a change fails in MySQL, the DDL statements it
+/// executed before the failure stay applied, and so does a whole change whose deploy log update
+/// fails, while the deploy log does not record them. The failed change has to be repaired by hand
+/// before migrating again, which is why MySQL changes are best kept to a single DDL statement.
+///
+/// The changes of a schema directory with a physical schema in `schema_mapping` are executed with
+/// that schema as the default schema, and with their qualified names mapped to it. In MySQL, where
+/// the default database outlives the transaction, the other changes are executed in the default
+/// database of the connection string, which has to select one when `schema_mapping` is set. The
+/// deploy log
+/// keeps the schema directory names, so the same source code is compared alike in every environment,
+/// and records the physical schema of each change in its `physical_schema` column.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `changeset` - The changeset with the changes to apply, in execution order.
+/// * `deploy_execution_id` - The id of the `deploy_execution` row the changes belong to, if any.
+/// * `schema_mapping` - The physical schema of each schema directory deployed elsewhere.
+/// * `dialect` - The SQL dialect the statements are parsed with to map their schema names.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(usize)` with the number of changes applied.
+/// * `Err(Box<dyn Error>)` if a change could not be applied.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * There is an issue connecting to the database.
+/// * A statement of a change fails to execute, the error names the change that failed.
+/// * The deploy log cannot be updated.
+pub async fn apply_changeset(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    changeset: &ChangeSet,
+    deploy_execution_id: Option<i64>,
+    schema_mapping: &HashMap<String, String>,
+    dialect: SqlDialect,
+) -> Result<usize, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+    let default_database = default_database(&pool, metadata.kind).await?;
+
+    let mut applied = 0;
+    for change in changeset.pending() {
+        log::info!("Applying change {} ({})", change.key, change.action);
+        apply_change(
+            &pool,
+            metadata,
+            change,
+            deploy_execution_id,
+            schema_mapping,
+            default_database.as_deref(),
+            dialect,
+        )
+        .await
+        .map_err(|e| format!("Failed to apply change '{}': {}", change.key, e))?;
+        applied += 1;
+    }
+
+    pool.close().await;
+    Ok(applied)
+}
+
+/// Executes the statements of a change and updates the deploy log in a single transaction.
+async fn apply_change(
+    pool: &AnyPool,
+    metadata: &MetadataContext,
+    change: &Change,
+    deploy_execution_id: Option<i64>,
+    schema_mapping: &HashMap<String, String>,
+    default_database: Option<&str>,
+    dialect: SqlDialect,
+) -> Result<(), Box<dyn Error>> {
+    let object = &change.object;
+    let mut tx = pool.begin().await?;
+
+    set_physical_schema(
+        &mut tx,
+        metadata.kind,
+        &object.schema_name,
+        schema_mapping,
+        default_database,
+    )
+    .await?;
+    for statement in change.statements()? {
+        let statement = map_schema_names(&statement, schema_mapping, dialect)?;
+        log::debug!("Executing: {}", statement);
+        tx.execute(statement.as_str()).await?;
+    }
+
+    if matches!(change.action, ChangeAction::Redeploy | ChangeAction::Drop) {
+        delete_deploy_log_row(&mut tx, metadata, object).await?;
+    }
+    if matches!(change.action, ChangeAction::Deploy | ChangeAction::Redeploy) {
+        let replaces_definition = change.action == ChangeAction::Redeploy || change.cause.is_some();
+        let rollback_content = object
+            .rollback_content
+            .as_ref()
+            .filter(|_| !replaces_definition);
+        sqlx::query(&format_query_with_schema(
+            "INSERT INTO {schema_prefix}deploy_log
+                (change_name, object_name, change_type, content_hash, rollback_content,
+                 deploy_execution_id, physical_schema)
+                VALUES ($1, $2, $3, $4, $5, $6, $7);",
+            metadata,
+        ))
+        .bind(object.short_change_name())
+        .bind(object.qualified_object_name())
+        .bind(&object.object_type)
+        .bind(&object.content_hash)
+        .bind(rollback_content)
+        .bind(deploy_execution_id)
+        .bind(
+            schema_mapping
+                .get(&object.schema_name)
+                .unwrap_or(&object.schema_name),
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Rolls back the changes applied after a deploy execution.
+///
+/// The deploy log rows whose `deploy_execution_id` is greater than the given id are undone in the
+/// reverse execution order of the source code, so that every change is undone before the changes
+/// it depends on, whatever the order of the deploy log rows. The changes that are no longer in the
+/// source code are undone first, from the last deploy log row to the first one. Each change runs
+/// its `rollback_content` and deletes its deploy log row in a single transaction, in the physical
+/// schema recorded in its deploy log row.
+///
+/// Nothing is rolled back if any of these changes has no rollback, which includes the redeployed
+/// changes and the changes recreated with them, whose previous definition is not kept.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `deploy_log` - The deploy log entries of the environment, in deploy order.
+/// * `source_code` - The changes of the source code, in execution order.
+/// * `deploy_execution_id` - The id of the last deploy execution to keep.
+/// * `schema_mapping` - The physical schema of each schema directory deployed elsewhere.
+/// * `dialect` - The SQL dialect the rollbacks are parsed with to map their schema names.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(usize)` with the number of changes rolled back.
+/// * `Err(Box<dyn Error>)` if the changes could not be rolled back.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * There is an issue connecting to the database.
+/// * A change to roll back has no rollback, the error lists every such change.
+/// * A rollback fails to execute, the error names the change that failed.
+pub async fn rollback_to(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    deploy_log: &IndexMap<String, DatabaseObject>,
+    source_code: &IndexMap<String, DatabaseObject>,
+    deploy_execution_id: i64,
+    schema_mapping: &HashMap<String, String>,
+    dialect: SqlDialect,
+) -> Result<usize, Box<dyn Error>> {
+    let changes = rollback_order(deploy_log, source_code, deploy_execution_id);
+
+    let missing: Vec<&str> = changes
+        .iter()
+        .filter(|object| object.rollback_content.is_none())
+        .map(|object| object.change_name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Cannot roll back to deploy execution {}, changes without rollback: {} (redeployed and recreated changes have none)",
+            deploy_execution_id,
+            missing.join(", ")
+        )
+        .into());
+    }
+
+    let pool = AnyPool::connect(connection_string).await?;
+    let default_database = default_database(&pool, metadata.kind).await?;
+
+    for object in &changes {
+        log::info!("Rolling back change {}", object.change_name);
+        rollback_change(
+            &pool,
+            metadata,
+            object,
+            schema_mapping,
+            default_database.as_deref(),
+            dialect,
+        )
+        .await
+        .map_err(|e| format!("Failed to roll back change '{}': {}", object.change_name, e))?;
+    }
+
+    pool.close().await;
+    Ok(changes.len())
+}
+
+/// Returns the deploy log entries applied after a deploy execution, in the order to roll them
+/// back: the changes missing from the source code from the last deploy log row to the first one,
+/// then the others in the reverse execution order of the source code.
+fn rollback_order<'a>(
+    deploy_log: &'a IndexMap<String, DatabaseObject>,
+    source_code: &IndexMap<String, DatabaseObject>,
+    deploy_execution_id: i64,
+) -> Vec<&'a DatabaseObject> {
+    let mut changes: Vec<&DatabaseObject> = deploy_log
+        .values()
+        .rev()
+        .filter(|object| {
+            object
+                .deploy_execution_id
+                .is_some_and(|id| id > deploy_execution_id)
+        })
+        .collect();
+    // The sort is stable and orders the changes missing from the source code first
+    changes.sort_by_key(|object| source_code.get_index_of(&object.change_name).map(Reverse));
+    changes
+}
+
+/// Executes the rollback of a deployed change and deletes its deploy log row in a single
+/// transaction.
+async fn rollback_change(
+    pool: &AnyPool,
+    metadata: &MetadataContext,
+    object: &DatabaseObject,
+    schema_mapping: &HashMap<String, String>,
+    default_database: Option<&str>,
+    dialect: SqlDialect,
+) -> Result<(), Box<dyn Error>> {
+    // The change is undone where it was deployed, even if the schema mapping changed since
+    let mut schema_mapping = schema_mapping.clone();
+    if let Some(physical_schema) = &object.physical_schema {
+        schema_mapping.insert(object.schema_name.clone(), physical_schema.clone());
+    }
+
+    let mut tx = pool.begin().await?;
+
+    set_physical_schema(
+        &mut tx,
+        metadata.kind,
+        &object.schema_name,
+        &schema_mapping,
+        default_database,
+    )
+    .await?;
+    if let Some(rollback) = &object.rollback_content {
+        let rollback = map_schema_names(rollback, &schema_mapping, dialect)?;
+        log::debug!("Executing: {}", rollback);
+        tx.execute(rollback.as_str()).await?;
+    }
+    delete_deploy_log_row(&mut tx, metadata, object).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns the default database of the connections to a MySQL database, `None` for the other
+/// kinds of databases or when the connection string selects no database.
+///
+/// The pool must not have executed any change yet, since `USE` changes the default database of
+/// its connections.
+async fn default_database(
+    pool: &AnyPool,
+    kind: DatabaseKind,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if kind != DatabaseKind::MySql {
+        return Ok(None);
+    }
+    Ok(sqlx::query_scalar("SELECT DATABASE();")
+        .fetch_one(pool)
+        .await?)
+}
+
+/// Makes the physical schema of a schema directory the default schema of a transaction, so the
+/// unqualified names of its changes are created there.
+async fn set_physical_schema(
+    tx: &mut Transaction<'_, Any>,
+    kind: DatabaseKind,
+    schema_name: &str,
+    schema_mapping: &HashMap<String, String>,
+    default_database: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let Some(statement) =
+        default_schema_statement(kind, schema_name, schema_mapping, default_database)?
+    else {
+        return Ok(());
+    };
+    log::debug!("Executing: {}", statement);
+    tx.execute(statement.as_str()).await?;
+    Ok(())
+}
+
+/// Returns the statement that makes the physical schema of a schema directory the default schema
+/// of a transaction, if any.
+///
+/// In PostgreSQL, `SET LOCAL` ends with the transaction, so nothing is done for the schema
+/// directories without a physical schema. In MySQL, `USE` stays on the pooled connection after
+/// the transaction, so the changes of these schema directories `USE` the default database of the
+/// connection string again. Nothing is done in SQLite, whose attached databases cannot be made the
+/// default.
+///
+/// # Errors
+///
+/// This function returns an error in MySQL for a schema directory without a physical schema when
+/// other schema directories have one and the connection string selects no database, since the
+/// default database could not be restored.
+fn default_schema_statement(
+    kind: DatabaseKind,
+    schema_name: &str,
+    schema_mapping: &HashMap<String, String>,
+    default_database: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let physical_schema = schema_mapping.get(schema_name);
+    let statement = match (kind, physical_schema) {
+        (DatabaseKind::PostgreSql, Some(physical_schema)) => {
+            format!("SET LOCAL search_path TO \"{}\";", physical_schema)
+        }
+        (DatabaseKind::MySql, Some(physical_schema)) => format!("USE `{}`;", physical_schema),
+        (DatabaseKind::MySql, None) if !schema_mapping.is_empty() => match default_database {
+            Some(database) => format!("USE `{}`;", database),
+            None => {
+                return Err(format!(
+                    "Cannot execute the changes of schema directory '{}' in the default database, the connection string selects none and other schema directories are mapped",
+                    schema_name
+                )
+                .into())
+            }
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(statement))
+}
+
+/// Deletes the deploy log row of a change.
+async fn delete_deploy_log_row(
+    tx: &mut Transaction<'_, Any>,
+    metadata: &MetadataContext,
+    object: &DatabaseObject,
+) -> Result<(), Box<dyn Error>> {
+    sqlx::query(&format_query_with_schema(
+        "DELETE FROM {schema_prefix}deploy_log
+            WHERE change_name = $1 AND object_name = $2 AND change_type = $3;",
+        metadata,
+    ))
+    .bind(object.short_change_name())
+    .bind(object.qualified_object_name())
+    .bind(&object.object_type)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::compute_changeset;
+    use crate::changeset::tests::{object, objects};
+    use crate::deploy_log::read_deploy_log;
+    use crate::deploy_log::tests::initialized_sqlite_database;
+    use indexmap::IndexMap;
+    use sqlx::query_scalar;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_apply_changeset_updates_deploy_log() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+
+        let source: IndexMap<String, DatabaseObject> = vec![
+            object("main.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object(
+                "main.table.t1.change1",
+                "ALTER TABLE t1 ADD COLUMN name TEXT;",
+            ),
+        ]
+        .into_iter()
+        .map(|object| (object.change_name.clone(), object))
+        .collect();
+        let changeset = compute_changeset(&source, &IndexMap::new())?;
+
+        let applied = apply_changeset(
+            &connection_string,
+            &metadata,
+            &changeset,
+            Some(7),
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await?;
+        assert_eq!(applied, 2);
+
+        let pool = AnyPool::connect(&connection_string).await?;
+        let names: Vec<String> = query_scalar("SELECT change_name FROM deploy_log ORDER BY id;")
+            .fetch_all(&pool)
+            .await?;
+        assert_eq!(names, vec!["change0", "change1"]);
+        let object_name: String = query_scalar("SELECT object_name FROM deploy_log WHERE id = 1;")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(object_name, "main.t1");
+        let execution_id: i64 =
+            query_scalar("SELECT deploy_execution_id FROM deploy_log WHERE id = 2;")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(execution_id, 7);
+        let columns: i64 = query_scalar("SELECT COUNT(*) FROM pragma_table_info('t1');")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(columns, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_change_is_not_logged() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+
+        let source: IndexMap<String, DatabaseObject> = vec![
+            object("main.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object(
+                "main.table.t1.change1",
+                "ALTER TABLE missing ADD COLUMN name TEXT;",
+            ),
+        ]
+        .into_iter()
+        .map(|object| (object.change_name.clone(), object))
+        .collect();
+        let changeset = compute_changeset(&source, &IndexMap::new())?;
+
+        let result = apply_changeset(
+            &connection_string,
+            &metadata,
+            &changeset,
+            None,
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to apply change 'main.table.t1.change1'"));
+
+        let pool = AnyPool::connect(&connection_string).await?;
+        let names: Vec<String> = query_scalar("SELECT change_name FROM deploy_log ORDER BY id;")
+            .fetch_all(&pool)
+            .await?;
+        assert_eq!(names, vec!["change0"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rollback_to_deploy_execution() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+
+        let mut create = object("main.table.t1.change0", "CREATE TABLE t1 (id INT);");
+        create.rollback_content = Some("DROP TABLE t1;".to_string());
+        let mut alter = object(
+            "main.table.t1.change1",
+            "ALTER TABLE t1 ADD COLUMN name TEXT;",
+        );
+        alter.rollback_content = Some("ALTER TABLE t1 DROP COLUMN name;".to_string());
+        let mut source: IndexMap<String, DatabaseObject> = IndexMap::new();
+        source.insert(create.change_name.clone(), create);
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &IndexMap::new())?,
+            Some(1),
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await?;
+        let deployed = read_deploy_log(&connection_string, &metadata).await?;
+        source.insert(alter.change_name.clone(), alter);
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &deployed)?,
+            Some(2),
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await?;
+
+        assert_eq!(
+            rollback_to(
+                &connection_string,
+                &metadata,
+                &read_deploy_log(&connection_string, &metadata).await?,
+                &source,
+                1,
+                &HashMap::new(),
+                SqlDialect::PostgreSql
+            )
+            .await?,
+            1
+        );
+        let pool = AnyPool::connect(&connection_string).await?;
+        let columns: i64 = query_scalar("SELECT COUNT(*) FROM pragma_table_info('t1');")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(columns, 1);
+
+        assert_eq!(
+            rollback_to(
+                &connection_string,
+                &metadata,
+                &read_deploy_log(&connection_string, &metadata).await?,
+                &source,
+                0,
+                &HashMap::new(),
+                SqlDialect::PostgreSql
+            )
+            .await?,
+            1
+        );
+        let tables: i64 = query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 't1';")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(tables, 0);
+        let rows: i64 = query_scalar("SELECT COUNT(*) FROM deploy_log;")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(rows, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rollback_requires_every_rollback() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+
+        let source: IndexMap<String, DatabaseObject> =
+            vec![object("main.table.t1.change0", "CREATE TABLE t1 (id INT);")]
+                .into_iter()
+                .map(|object| (object.change_name.clone(), object))
+                .collect();
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &IndexMap::new())?,
+            Some(1),
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await?;
+
+        let error = rollback_to(
+            &connection_string,
+            &metadata,
+            &read_deploy_log(&connection_string, &metadata).await?,
+            &source,
+            0,
+            &HashMap::new(),
+            SqlDialect::PostgreSql,
+        )
+        .await
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("changes without rollback: main.table.t1.change0"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_schema_mapping_is_applied_when_executing() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+        let schema_mapping = HashMap::from([("app".to_string(), "main".to_string())]);
+
+        let mut create = object("main.table.t1.change0", "CREATE TABLE app.t1 (id INT);");
+        create.change_name = "app.table.t1.change0".to_string();
+        create.schema_name = "app".to_string();
+        create.rollback_content = Some("DROP TABLE app.t1;".to_string());
+        let source: IndexMap<String, DatabaseObject> =
+            IndexMap::from([(create.change_name.clone(), create)]);
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &IndexMap::new())?,
+            Some(1),
+            &schema_mapping,
+            SqlDialect::Sqlite,
+        )
+        .await?;
+
+        let pool = AnyPool::connect(&connection_string).await?;
+        let (object_name, physical_schema): (String, String) =
+            sqlx::query_as("SELECT object_name, physical_schema FROM deploy_log;")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(
+            (object_name.as_str(), physical_schema.as_str()),
+            ("app.t1", "main")
+        );
+        let tables: i64 =
+            query_scalar("SELECT COUNT(*) FROM main.sqlite_master WHERE name = 't1';")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(tables, 1);
+
+        assert_eq!(
+            // The change is rolled back in its recorded physical schema without the mapping
+            rollback_to(
+                &connection_string,
+                &metadata,
+                &read_deploy_log(&connection_string, &metadata).await?,
+                &source,
+                0,
+                &HashMap::new(),
+                SqlDialect::Sqlite
+            )
+            .await?,
+            1
+        );
+        let tables: i64 =
+            query_scalar("SELECT COUNT(*) FROM main.sqlite_master WHERE name = 't1';")
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(tables, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_order_follows_the_source_code() {
+        let source = objects(vec![
+            object("main.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("main.view.v1.root0", "CREATE VIEW v1 AS SELECT id FROM t1;"),
+        ]);
+        // The view was logged before its table, t0 was deployed before the deploy execution to
+        // keep and t2 is no longer in the source code
+        let mut log = objects(vec![
+            object("main.view.v1.root0", "CREATE VIEW v1 AS SELECT id FROM t1;"),
+            object("main.table.t0.change0", "CREATE TABLE t0 (id INT);"),
+            object("main.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("main.table.t2.change0", "CREATE TABLE t2 (id INT);"),
+        ]);
+        for object in log.values_mut() {
+            object.deploy_execution_id = Some(2);
+        }
+        log["main.table.t0.change0"].deploy_execution_id = Some(1);
+
+        let order: Vec<&str> = rollback_order(&log, &source, 1)
+            .into_iter()
+            .map(|object| object.change_name.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "main.table.t2.change0",
+                "main.view.v1.root0",
+                "main.table.t1.change0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_database_is_restored_after_a_mapped_change() -> Result<(), Box<dyn Error>> {
+        let schema_mapping = HashMap::from([("app".to_string(), "app_dev1".to_string())]);
+        // A change of the mapped app directory followed by one of the unmapped audit directory
+        let statements = |kind, default_database| {
+            ["app", "audit"]
+                .iter()
+                .map(|schema| {
+                    default_schema_statement(kind, schema, &schema_mapping, default_database)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        assert_eq!(
+            statements(DatabaseKind::MySql, Some("shop"))?,
+            vec![
+                Some("USE `app_dev1`;".to_string()),
+                Some("USE `shop`;".to_string())
+            ]
+        );
+        assert_eq!(
+            statements(DatabaseKind::PostgreSql, None)?,
+            vec![
+                Some("SET LOCAL search_path TO \"app_dev1\";".to_string()),
+                None
+            ]
+        );
+        assert_eq!(statements(DatabaseKind::Sqlite, None)?, vec![None, None]);
+        assert!(statements(DatabaseKind::MySql, None)
+            .unwrap_err()
+            .to_string()
+            .contains("schema directory 'audit'"));
+        assert_eq!(
+            default_schema_statement(DatabaseKind::MySql, "audit", &HashMap::new(), None)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_redeployed_changes_cannot_be_rolled_back() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+
+        let mut view = object("main.view.v1.root0", "CREATE VIEW v1 AS SELECT 1 AS id;");
+        view.rollback_content = Some("DROP VIEW v1;".to_string());
+        let mut source = objects(vec![view.clone()]);
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &IndexMap::new())?,
+            Some(1),
+            &HashMap::new(),
+            SqlDialect::Sqlite,
+        )
+        .await?;
+        let deployed = read_deploy_log(&connection_string, &metadata).await?;
+        assert!(deployed["main.view.v1.root0"].rollback_content.is_some());
+
+        view.value = "CREATE VIEW v1 AS SELECT 2 AS id;".to_string();
+        view.content_hash = "modified".to_string();
+        source = objects(vec![view]);
+        apply_changeset(
+            &connection_string,
+            &metadata,
+            &compute_changeset(&source, &deployed)?,
+            Some(2),
+            &HashMap::new(),
+            SqlDialect::Sqlite,
+        )
+        .await?;
+
+        // Dropping the view would not restore its first definition
+        let deploy_log = read_deploy_log(&connection_string, &metadata).await?;
+        assert_eq!(deploy_log["main.view.v1.root0"].rollback_content, None);
+        let error = rollback_to(
+            &connection_string,
+            &metadata,
+            &deploy_log,
+            &source,
+            1,
+            &HashMap::new(),
+            SqlDialect::Sqlite,
+        )
+        .await
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("changes without rollback: main.view.v1.root0"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+mod mysql;
+mod postgres;
+mod sqlite;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::utils::DatabaseKind;
+
+/// A database object read from the catalog of the target database, ready to be written to the
+/// `schema/object_type/object_name.sql` file that `read_source_code` expects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedObject {
+    /// The schema the object belongs to.
+    pub schema_name: String,
+    /// The object type directory of the object (e.g. table, view).
+    pub object_type: String,
+    /// The name of the object, used as the file name.
+    pub object_name: String,
+    /// The changes of the object, in execution order.
+    pub changes: Vec<GeneratedChange>,
+}
+
+/// A change of a generated object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedChange {
+    /// The name of the change, written in a `//// CHANGE name=` line. Changes without a name are
+    /// only terminated by `GO`, as it is done for stateless objects such as functions.
+    pub name: Option<String>,
+    /// The SQL of the change.
+    pub sql: String,
+}
+
+impl GeneratedObject {
+    /// Renders the object as the content of its source code file.
+    pub fn render(&self) -> String {
+        let mut content = String::new();
+        for change in &self.changes {
+            if let Some(name) = &change.name {
+                content.push_str(&format!("//// CHANGE name={}\n", name));
+            }
+            content.push_str(change.sql.trim());
+            content.push_str("\nGO\n\n");
+        }
+        content.trim_end().to_string() + "\n"
+    }
+}
+
+/// A table read from the catalog, rendered as a `CREATE TABLE` change followed by one change per
+/// constraint and per column default that depends on another object.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableDefinition {
+    /// The schema the table belongs to.
+    pub schema_name: String,
+    /// The name of the table qualified by its schema, as it has to be written in SQL.
+    pub table_name: String,
+    /// The columns of the table, in order.
+    pub columns: Vec<ColumnDefinition>,
+    /// The `(column, expression)` defaults set after the table is created, such as the defaults
+    /// that use a sequence.
+    pub column_defaults: Vec<(String, String)>,
+    /// The `(name, definition)` constraints of the table, in execution order.
+    pub constraints: Vec<(String, String)>,
+}
+
+/// A column of a table read from the catalog.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnDefinition {
+    /// The name of the column, as it has to be written in SQL.
+    pub name: String,
+    /// The data type of the column, including any clause that follows the type.
+    pub data_type: String,
+    /// Whether the column has a `NOT NULL` constraint.
+    pub not_null: bool,
+    /// The default expression of the column.
+    pub default: Option<String>,
+}
+
+impl TableDefinition {
+    /// Converts the table into a generated object with one `//// CHANGE` block per statement.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_name` - The name of the table, without quoting, used as the file name.
+    pub fn into_object(self, file_name: &str) -> GeneratedObject {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut definition = format!("    {} {}", column.name, column.data_type);
+                if let Some(default) = &column.default {
+                    definition.push_str(&format!(" DEFAULT {}", default));
+                }
+                if column.not_null {
+                    definition.push_str(" NOT NULL");
+                }
+                definition
+            })
+            .collect();
+
+        let mut statements = vec![format!(
+            "CREATE TABLE {} (\n{}\n);",
+            self.table_name,
+            columns.join(",\n")
+        )];
+        for (name, definition) in &self.constraints {
+            statements.push(format!(
+                "ALTER TABLE {}\n    ADD CONSTRAINT {} {};",
+                self.table_name, name, definition
+            ));
+        }
+        for (column, default) in &self.column_defaults {
+            statements.push(format!(
+                "ALTER TABLE {} ALTER COLUMN {} SET DEFAULT {};",
+                self.table_name, column, default
+            ));
+        }
+
+        GeneratedObject {
+            schema_name: self.schema_name,
+            object_type: "table".to_string(),
+            object_name: file_name.to_string(),
+            changes: statements
+                .into_iter()
+                .enumerate()
+                .map(|(i, sql)| GeneratedChange {
+                    name: Some(format!("change{}", i)),
+                    sql,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Writes the generated objects into the `schema/object_type/object_name.sql` layout under the
+/// base directory, replacing the files that already exist.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the directory where the files are written.
+/// * `objects` - The objects to write.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(usize)` with the number of files written.
+/// * `Err(Box<dyn Error>)` if a directory or a file cannot be written.
+pub fn write_objects(base_dir: &str, objects: &[GeneratedObject]) -> Result<usize, Box<dyn Error>> {
+    for object in objects {
+        let dir = Path::new(base_dir)
+            .join(&object.schema_name)
+            .join(&object.object_type);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join(format!("{}.sql", object.object_name));
+        log::debug!("Writing {}", file_path.display());
+        fs::write(file_path, object.render())?;
+    }
+    Ok(objects.len())
+}
+
+/// Reads the catalog of the target database and writes the source code of every object into the
+/// base directory.
+///
+/// The catalog is read by the reader of the database behind the connection string, PostgreSQL,
+/// MySQL or SQLite, and every reader produces the same directory layout.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the directory where the files are written.
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata_schema` - The schema of the deploy log tables, which is not generated.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(usize)` with the number of files written.
+/// * `Err(Box<dyn Error>)` if the catalog cannot be read or the files cannot be written.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The scheme of the connection string is not supported.
+/// * There is an issue connecting to the database or querying its catalog.
+/// * A directory or a file cannot be written.
+pub async fn generate_source_code(
+    base_dir: &str,
+    connection_string: &str,
+    metadata_schema: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let objects = match DatabaseKind::from_connection_string(connection_string)? {
+        DatabaseKind::PostgreSql => {
+            postgres::read_catalog(connection_string, metadata_schema).await?
+        }
+        DatabaseKind::MySql => mysql::read_catalog(connection_string, metadata_schema).await?,
+        DatabaseKind::Sqlite => sqlite::read_catalog(connection_string).await?,
+    };
+
+    write_objects(base_dir, &objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_code::read_source_code;
+
+    fn table_a() -> TableDefinition {
+        TableDefinition {
+            schema_name: "public".to_string(),
+            table_name: "public.table_a".to_string(),
+            columns: vec![
+                ColumnDefinition {
+                    name: "a_id".to_string(),
+                    data_type: "integer".to_string(),
+                    not_null: true,
+                    default: None,
+                },
+                ColumnDefinition {
+                    name: "a2_id".to_string(),
+                    data_type: "integer".to_string(),
+                    not_null: false,
+                    default: Some("0".to_string()),
+                },
+            ],
+            column_defaults: vec![(
+                "a_id".to_string(),
+                "nextval('public.table_a_a_id_seq'::regclass)".to_string(),
+            )],
+            constraints: vec![("table_a_pkey".to_string(), "PRIMARY KEY (a_id)".to_string())],
+        }
+    }
+
+    #[test]
+    fn test_render_table_as_changes() {
+        let object = table_a().into_object("table_a");
+        assert_eq!(object.object_type, "table");
+        assert_eq!(
+            object.render(),
+            "//// CHANGE name=change0\n\
+             CREATE TABLE public.table_a (\n    a_id integer NOT NULL,\n    a2_id integer DEFAULT 0\n);\nGO\n\n\
+             //// CHANGE name=change1\n\
+             ALTER TABLE public.table_a\n    ADD CONSTRAINT table_a_pkey PRIMARY KEY (a_id);\nGO\n\n\
+             //// CHANGE name=change2\n\
+             ALTER TABLE public.table_a ALTER COLUMN a_id SET DEFAULT nextval('public.table_a_a_id_seq'::regclass);\nGO\n"
+        );
+    }
+
+    #[test]
+    fn test_render_object_without_change_names() {
+        let object = GeneratedObject {
+            schema_name: "public".to_string(),
+            object_type: "function".to_string(),
+            object_name: "f1".to_string(),
+            changes: vec![
+                GeneratedChange {
+                    name: None,
+                    sql: "CREATE FUNCTION f1() RETURNS integer LANGUAGE sql AS 'SELECT 1';"
+                        .to_string(),
+                },
+                GeneratedChange {
+                    name: None,
+                    sql:
+                        "CREATE FUNCTION f1(a integer) RETURNS integer LANGUAGE sql AS 'SELECT 1';"
+                            .to_string(),
+                },
+            ],
+        };
+        assert_eq!(object.render().matches("\nGO\n").count(), 2);
+        assert!(!object.render().contains("//// CHANGE"));
+    }
+
+    #[test]
+    fn test_written_objects_are_readable_source_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().to_str().unwrap();
+        // A table of another schema referencing the table of the public schema
+        let orders = TableDefinition {
+            schema_name: "sales".to_string(),
+            table_name: "sales.orders".to_string(),
+            columns: vec![ColumnDefinition {
+                name: "a_id".to_string(),
+                data_type: "integer".to_string(),
+                not_null: true,
+                default: None,
+            }],
+            column_defaults: vec![],
+            constraints: vec![(
+                "orders_a_id_fkey".to_string(),
+                "FOREIGN KEY (a_id) REFERENCES public.table_a(a_id)".to_string(),
+            )],
+        };
+        let objects = vec![
+            table_a().into_object("table_a"),
+            orders.into_object("orders"),
+        ];
+
+        assert_eq!(write_objects(base_dir, &objects).unwrap(), 2);
+
+        let source_code = read_source_code(base_dir, None).unwrap();
+        assert_eq!(source_code.len(), 5);
+        assert!(source_code.contains_key("public.table.table_a.change2"));
+        let orders = &source_code["sales.table.orders.change0"];
+        assert_eq!(orders.schema_name, "sales");
+        assert!(orders.value.starts_with("CREATE TABLE sales.orders ("));
+        assert!(source_code["sales.table.orders.change1"]
+            .dependency_keys
+            .contains(&"public.table.table_a.change0".to_string()));
+    }
+}
@@ -0,0 +1,517 @@
+use sqlx::{query, AnyPool, Row};
+use std::error::Error;
+
+use super::{ColumnDefinition, GeneratedChange, GeneratedObject, TableDefinition};
+
+/// Returns the databases that belong to MySQL or to oxigration and are never generated, with the
+/// metadata schema escaped as a string literal, backslashes included.
+fn excluded_schemas(metadata_schema: &str) -> String {
+    format!(
+        "('mysql', 'sys', 'information_schema', 'performance_schema', '{}')",
+        metadata_schema.replace('\\', "\\\\").replace('\'', "''")
+    )
+}
+
+/// Reads the tables, indexes, views and functions of every user database of a MySQL server from
+/// `information_schema`.
+///
+/// MySQL databases are written as schema directories, and every object is written with its name
+/// qualified by its database, so that it is deployed to the database of its directory. Procedures
+/// are skipped with a warning, as it is done for PostgreSQL.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata_schema` - The schema of the deploy log tables, which is not generated.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Vec<GeneratedObject>)` with the objects ordered by schema, type and name.
+/// * `Err(Box<dyn Error>)` if the catalog cannot be queried.
+pub async fn read_catalog(
+    connection_string: &str,
+    metadata_schema: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+    let excluded = excluded_schemas(metadata_schema);
+
+    let mut objects = Vec::new();
+    objects.extend(read_tables(&pool, &excluded).await?);
+    objects.extend(read_indexes(&pool, &excluded).await?);
+    objects.extend(read_views(&pool, &excluded).await?);
+    objects.extend(read_functions(&pool, &excluded).await?);
+
+    pool.close().await;
+    Ok(objects)
+}
+
+/// Quotes an identifier with backticks when it is not a plain lowercase identifier.
+fn quote_ident(name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if plain {
+        name.to_string()
+    } else {
+        format!("`{}`", name.replace('`', "``"))
+    }
+}
+
+/// Renders the default value of a column as it has to be written in a column definition.
+///
+/// `information_schema` returns literal defaults without quotes, and expression defaults flagged
+/// with `DEFAULT_GENERATED` in the `EXTRA` column.
+fn column_default(default: &str, data_type: &str, extra: &str) -> String {
+    let numeric = [
+        "int", "decimal", "numeric", "float", "double", "real", "bit", "bool",
+    ]
+    .iter()
+    .any(|numeric_type| data_type.contains(numeric_type));
+
+    if default.eq_ignore_ascii_case("CURRENT_TIMESTAMP")
+        || default.starts_with("CURRENT_TIMESTAMP(")
+    {
+        default.to_string()
+    } else if extra.contains("DEFAULT_GENERATED") {
+        format!("({})", default)
+    } else if numeric {
+        default.to_string()
+    } else {
+        format!("'{}'", default.replace('\'', "''"))
+    }
+}
+
+/// Returns the name of an object qualified by its database, as it has to be written in SQL.
+fn qualified_name(schema_name: &str, name: &str) -> String {
+    format!("{}.{}", quote_ident(schema_name), quote_ident(name))
+}
+
+/// Reads the tables with their columns and constraints.
+async fn read_tables(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let tables = query(&format!(
+        "SELECT CAST(TABLE_SCHEMA AS CHAR) AS schema_name, CAST(TABLE_NAME AS CHAR) AS table_name
+            FROM information_schema.TABLES
+            WHERE TABLE_TYPE = 'BASE TABLE' AND TABLE_SCHEMA NOT IN {}
+            ORDER BY TABLE_SCHEMA, TABLE_NAME;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut objects = Vec::new();
+    for table in tables {
+        let schema_name: String = table.try_get("schema_name")?;
+        let table_name: String = table.try_get("table_name")?;
+        let mut definition = TableDefinition {
+            schema_name: schema_name.clone(),
+            table_name: qualified_name(&schema_name, &table_name),
+            ..Default::default()
+        };
+
+        let columns = query(
+            "SELECT CAST(COLUMN_NAME AS CHAR) AS column_name,
+                    CAST(COLUMN_TYPE AS CHAR) AS data_type,
+                    CAST(IS_NULLABLE AS CHAR) AS is_nullable,
+                    CAST(COLUMN_DEFAULT AS CHAR) AS column_default,
+                    CAST(EXTRA AS CHAR) AS extra,
+                    CAST(GENERATION_EXPRESSION AS CHAR) AS generation_expression
+                FROM information_schema.COLUMNS
+                WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ?
+                ORDER BY ORDINAL_POSITION;",
+        )
+        .bind(&schema_name)
+        .bind(&table_name)
+        .fetch_all(pool)
+        .await?;
+
+        for column in columns {
+            let name: String = column.try_get("column_name")?;
+            let mut data_type: String = column.try_get("data_type")?;
+            let is_nullable: String = column.try_get("is_nullable")?;
+            let default: Option<String> = column.try_get("column_default")?;
+            let extra: String = column.try_get("extra")?;
+            let generation_expression: Option<String> = column.try_get("generation_expression")?;
+
+            let mut default = default.map(|default| column_default(&default, &data_type, &extra));
+            if extra.contains("auto_increment") {
+                data_type.push_str(" AUTO_INCREMENT");
+            }
+            if extra.contains("on update CURRENT_TIMESTAMP") {
+                data_type.push_str(" ON UPDATE CURRENT_TIMESTAMP");
+            }
+            if let Some(expression) = generation_expression.filter(|e| !e.is_empty()) {
+                let storage = if extra.contains("STORED") {
+                    "STORED"
+                } else {
+                    "VIRTUAL"
+                };
+                data_type.push_str(&format!(
+                    " GENERATED ALWAYS AS ({}) {}",
+                    expression, storage
+                ));
+                default = None;
+            }
+
+            definition.columns.push(ColumnDefinition {
+                name: quote_ident(&name),
+                data_type,
+                not_null: is_nullable == "NO",
+                default,
+            });
+        }
+
+        definition
+            .constraints
+            .extend(read_constraints(pool, &schema_name, &table_name).await?);
+
+        objects.push(definition.into_object(&table_name));
+    }
+
+    Ok(objects)
+}
+
+/// Reads the primary key, unique, check and foreign key constraints of a table, in that order.
+async fn read_constraints(
+    pool: &AnyPool,
+    schema_name: &str,
+    table_name: &str,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let constraints = query(
+        "SELECT CAST(tc.CONSTRAINT_NAME AS CHAR) AS constraint_name,
+                CAST(tc.CONSTRAINT_TYPE AS CHAR) AS constraint_type,
+                CAST(GROUP_CONCAT(k.COLUMN_NAME ORDER BY k.ORDINAL_POSITION) AS CHAR) AS columns,
+                CAST(MAX(k.REFERENCED_TABLE_SCHEMA) AS CHAR) AS referenced_schema,
+                CAST(MAX(k.REFERENCED_TABLE_NAME) AS CHAR) AS referenced_table,
+                CAST(GROUP_CONCAT(k.REFERENCED_COLUMN_NAME ORDER BY k.ORDINAL_POSITION) AS CHAR)
+                    AS referenced_columns,
+                CAST(MAX(r.UPDATE_RULE) AS CHAR) AS update_rule,
+                CAST(MAX(r.DELETE_RULE) AS CHAR) AS delete_rule,
+                CAST(MAX(c.CHECK_CLAUSE) AS CHAR) AS check_clause
+            FROM information_schema.TABLE_CONSTRAINTS tc
+            LEFT JOIN information_schema.KEY_COLUMN_USAGE k
+                ON k.CONSTRAINT_SCHEMA = tc.CONSTRAINT_SCHEMA
+                AND k.CONSTRAINT_NAME = tc.CONSTRAINT_NAME
+                AND k.TABLE_NAME = tc.TABLE_NAME
+            LEFT JOIN information_schema.REFERENTIAL_CONSTRAINTS r
+                ON r.CONSTRAINT_SCHEMA = tc.CONSTRAINT_SCHEMA
+                AND r.CONSTRAINT_NAME = tc.CONSTRAINT_NAME
+            LEFT JOIN information_schema.CHECK_CONSTRAINTS c
+                ON c.CONSTRAINT_SCHEMA = tc.CONSTRAINT_SCHEMA
+                AND c.CONSTRAINT_NAME = tc.CONSTRAINT_NAME
+            WHERE tc.TABLE_SCHEMA = ? AND tc.TABLE_NAME = ?
+            GROUP BY tc.CONSTRAINT_NAME, tc.CONSTRAINT_TYPE
+            ORDER BY CASE tc.CONSTRAINT_TYPE WHEN 'PRIMARY KEY' THEN 0 WHEN 'UNIQUE' THEN 1
+                                             WHEN 'CHECK' THEN 2 ELSE 3 END,
+                     tc.CONSTRAINT_NAME;",
+    )
+    .bind(schema_name)
+    .bind(table_name)
+    .fetch_all(pool)
+    .await?;
+
+    let quote_columns = |columns: Option<String>| {
+        columns
+            .unwrap_or_default()
+            .split(',')
+            .map(quote_ident)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut definitions = Vec::new();
+    for constraint in constraints {
+        let name: String = constraint.try_get("constraint_name")?;
+        let constraint_type: String = constraint.try_get("constraint_type")?;
+        let columns = quote_columns(constraint.try_get("columns")?);
+
+        let (name, definition) = match constraint_type.as_str() {
+            // The name of a MySQL primary key is always PRIMARY, which is a reserved word
+            "PRIMARY KEY" => (
+                format!("{}_pkey", table_name),
+                format!("PRIMARY KEY ({})", columns),
+            ),
+            "UNIQUE" => (name, format!("UNIQUE ({})", columns)),
+            "CHECK" => {
+                let check_clause: Option<String> = constraint.try_get("check_clause")?;
+                (
+                    name,
+                    format!("CHECK ({})", check_clause.unwrap_or_default()),
+                )
+            }
+            "FOREIGN KEY" => {
+                let referenced_schema: Option<String> = constraint.try_get("referenced_schema")?;
+                let referenced_table: Option<String> = constraint.try_get("referenced_table")?;
+                let update_rule: Option<String> = constraint.try_get("update_rule")?;
+                let delete_rule: Option<String> = constraint.try_get("delete_rule")?;
+                (
+                    name,
+                    format!(
+                        "FOREIGN KEY ({}) REFERENCES {} ({}) ON UPDATE {} ON DELETE {}",
+                        columns,
+                        qualified_name(
+                            &referenced_schema.unwrap_or_else(|| schema_name.to_string()),
+                            &referenced_table.unwrap_or_default()
+                        ),
+                        quote_columns(constraint.try_get("referenced_columns")?),
+                        update_rule.unwrap_or_else(|| "RESTRICT".to_string()),
+                        delete_rule.unwrap_or_else(|| "RESTRICT".to_string())
+                    ),
+                )
+            }
+            _ => continue,
+        };
+        definitions.push((quote_ident(&name), definition));
+    }
+
+    Ok(definitions)
+}
+
+/// Reads the indexes that are not created by a constraint.
+async fn read_indexes(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let indexes = query(&format!(
+        "SELECT CAST(s.TABLE_SCHEMA AS CHAR) AS schema_name,
+                CAST(s.TABLE_NAME AS CHAR) AS table_name,
+                CAST(s.INDEX_NAME AS CHAR) AS index_name,
+                MIN(s.NON_UNIQUE) AS non_unique,
+                CAST(GROUP_CONCAT(s.COLUMN_NAME ORDER BY s.SEQ_IN_INDEX) AS CHAR) AS columns
+            FROM information_schema.STATISTICS s
+            WHERE s.TABLE_SCHEMA NOT IN {}
+              AND s.INDEX_NAME <> 'PRIMARY'
+              AND NOT EXISTS (
+                  SELECT 1 FROM information_schema.TABLE_CONSTRAINTS tc
+                  WHERE tc.TABLE_SCHEMA = s.TABLE_SCHEMA
+                    AND tc.TABLE_NAME = s.TABLE_NAME
+                    AND tc.CONSTRAINT_NAME = s.INDEX_NAME)
+            GROUP BY s.TABLE_SCHEMA, s.TABLE_NAME, s.INDEX_NAME
+            ORDER BY s.TABLE_SCHEMA, s.INDEX_NAME;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    indexes
+        .iter()
+        .map(|index| {
+            let schema_name: String = index.try_get("schema_name")?;
+            let table_name: String = index.try_get("table_name")?;
+            let index_name: String = index.try_get("index_name")?;
+            let non_unique: i64 = index.try_get("non_unique")?;
+            let columns: String = index.try_get("columns")?;
+            Ok(GeneratedObject {
+                schema_name: schema_name.clone(),
+                object_type: "index".to_string(),
+                object_name: index_name.clone(),
+                changes: vec![GeneratedChange {
+                    name: Some("change0".to_string()),
+                    sql: format!(
+                        "CREATE {}INDEX {} ON {} ({});",
+                        if non_unique == 0 { "UNIQUE " } else { "" },
+                        quote_ident(&index_name),
+                        qualified_name(&schema_name, &table_name),
+                        columns
+                            .split(',')
+                            .map(quote_ident)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }],
+            })
+        })
+        .collect()
+}
+
+/// Reads the views.
+async fn read_views(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let views = query(&format!(
+        "SELECT CAST(TABLE_SCHEMA AS CHAR) AS schema_name, CAST(TABLE_NAME AS CHAR) AS view_name,
+                CAST(VIEW_DEFINITION AS CHAR) AS definition
+            FROM information_schema.VIEWS
+            WHERE TABLE_SCHEMA NOT IN {}
+            ORDER BY TABLE_SCHEMA, TABLE_NAME;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    views
+        .iter()
+        .map(|view| {
+            let schema_name: String = view.try_get("schema_name")?;
+            let view_name: String = view.try_get("view_name")?;
+            let definition: String = view.try_get("definition")?;
+            Ok(GeneratedObject {
+                schema_name: schema_name.clone(),
+                object_type: "view".to_string(),
+                object_name: view_name.clone(),
+                changes: vec![GeneratedChange {
+                    name: None,
+                    sql: format!(
+                        "CREATE VIEW {} AS\n{};",
+                        qualified_name(&schema_name, &view_name),
+                        definition
+                    ),
+                }],
+            })
+        })
+        .collect()
+}
+
+/// Reads the functions with `SHOW CREATE FUNCTION`, without their `DEFINER` clause so that the
+/// source code does not depend on the account that created them, and with their name qualified by
+/// their database.
+async fn read_functions(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let routines = query(&format!(
+        "SELECT CAST(ROUTINE_SCHEMA AS CHAR) AS schema_name,
+                CAST(ROUTINE_NAME AS CHAR) AS routine_name,
+                CAST(ROUTINE_TYPE AS CHAR) AS routine_type
+            FROM information_schema.ROUTINES
+            WHERE ROUTINE_SCHEMA NOT IN {}
+            ORDER BY ROUTINE_SCHEMA, ROUTINE_NAME;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut objects = Vec::new();
+    for routine in routines {
+        let schema_name: String = routine.try_get("schema_name")?;
+        let routine_name: String = routine.try_get("routine_name")?;
+        let routine_type: String = routine.try_get("routine_type")?;
+        if routine_type != "FUNCTION" {
+            log::warn!(
+                "Procedure {}.{} skipped, procedures cannot be generated yet",
+                schema_name,
+                routine_name
+            );
+            continue;
+        }
+
+        let row = query(&format!(
+            "SHOW CREATE FUNCTION {}.{};",
+            quote_ident(&schema_name),
+            quote_ident(&routine_name)
+        ))
+        .fetch_one(pool)
+        .await?;
+        let definition: Option<String> = row.try_get("Create Function")?;
+        let definition = definition.ok_or_else(|| {
+            format!(
+                "Not allowed to read the definition of function {}.{}",
+                schema_name, routine_name
+            )
+        })?;
+
+        let sql = format!(
+            "{};",
+            qualify_function(&strip_definer(&definition), &schema_name).trim_end()
+        );
+        objects.push(GeneratedObject {
+            schema_name,
+            object_type: "function".to_string(),
+            object_name: routine_name,
+            changes: vec![GeneratedChange { name: None, sql }],
+        });
+    }
+
+    Ok(objects)
+}
+
+/// Removes the `DEFINER=user@host` clause from a `CREATE` statement.
+fn strip_definer(definition: &str) -> String {
+    match definition.find(" DEFINER=") {
+        Some(start) => {
+            let rest = &definition[start + " DEFINER=".len()..];
+            let end = rest.find(' ').unwrap_or(rest.len());
+            format!("{}{}", &definition[..start], &rest[end..])
+        }
+        None => definition.to_string(),
+    }
+}
+
+/// Qualifies the name of the function of a `CREATE FUNCTION` statement with its database.
+fn qualify_function(definition: &str, schema_name: &str) -> String {
+    match definition.find(" FUNCTION ") {
+        Some(start) => {
+            let name = start + " FUNCTION ".len();
+            format!(
+                "{}{}.{}",
+                &definition[..name],
+                quote_ident(schema_name),
+                &definition[name..]
+            )
+        }
+        None => definition.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("table_a"), "table_a");
+        assert_eq!(quote_ident("TableA"), "`TableA`");
+        assert_eq!(quote_ident("1st"), "`1st`");
+    }
+
+    #[test]
+    fn test_excluded_schemas_escape_the_metadata_schema() {
+        assert_eq!(
+            excluded_schemas("oxi'gration"),
+            "('mysql', 'sys', 'information_schema', 'performance_schema', 'oxi''gration')"
+        );
+        assert_eq!(
+            excluded_schemas("oxi\\"),
+            "('mysql', 'sys', 'information_schema', 'performance_schema', 'oxi\\\\')"
+        );
+    }
+
+    #[test]
+    fn test_column_default() {
+        assert_eq!(column_default("0", "int", ""), "0");
+        assert_eq!(column_default("it's", "varchar(10)", ""), "'it''s'");
+        assert_eq!(
+            column_default("CURRENT_TIMESTAMP", "timestamp", "DEFAULT_GENERATED"),
+            "CURRENT_TIMESTAMP"
+        );
+        assert_eq!(
+            column_default("uuid()", "char(36)", "DEFAULT_GENERATED"),
+            "(uuid())"
+        );
+    }
+
+    #[test]
+    fn test_strip_definer() {
+        assert_eq!(
+            strip_definer("CREATE DEFINER=`root`@`%` FUNCTION `f1`() RETURNS int"),
+            "CREATE FUNCTION `f1`() RETURNS int"
+        );
+    }
+
+    #[test]
+    fn test_names_are_qualified_by_their_database() {
+        assert_eq!(qualified_name("shop", "Orders"), "shop.`Orders`");
+        assert_eq!(
+            qualify_function("CREATE FUNCTION `f1`() RETURNS int", "shop"),
+            "CREATE FUNCTION shop.`f1`() RETURNS int"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use sqlx::any::AnyPoolOptions;
+use sqlx::{query, AnyPool, Executor, Row};
+use std::error::Error;
+
+use super::{ColumnDefinition, GeneratedChange, GeneratedObject, TableDefinition};
+
+/// Returns the schemas that belong to PostgreSQL or to oxigration and are never generated, with the
+/// metadata schema escaped as a string literal.
+fn excluded_schemas(metadata_schema: &str) -> String {
+    format!(
+        "('pg_catalog', 'information_schema', 'pg_toast', '{}')",
+        metadata_schema.replace('\'', "''")
+    )
+}
+
+/// Reads the tables, indexes, sequences, views and functions of every user schema of a PostgreSQL
+/// database.
+///
+/// Objects that belong to an extension, and the sequences backing identity columns, are skipped
+/// since they are created by the extension or the table.
+///
+/// Every object is written with its schema-qualified name, so that it is deployed to the schema of
+/// its directory. The catalog is read with an empty `search_path`, which makes PostgreSQL qualify
+/// the names in the definitions it renders, such as view queries, foreign keys and defaults.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata_schema` - The schema of the deploy log tables, which is not generated.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Vec<GeneratedObject>)` with the objects ordered by schema, type and name.
+/// * `Err(Box<dyn Error>)` if the catalog cannot be queried.
+pub async fn read_catalog(
+    connection_string: &str,
+    metadata_schema: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let pool = AnyPoolOptions::new()
+        .after_connect(|connection, _| {
+            Box::pin(async move {
+                connection.execute("SET search_path TO '';").await?;
+                Ok(())
+            })
+        })
+        .connect(connection_string)
+        .await?;
+    let excluded = excluded_schemas(metadata_schema);
+
+    let mut objects = Vec::new();
+    objects.extend(read_tables(&pool, &excluded).await?);
+    objects.extend(read_indexes(&pool, &excluded).await?);
+    objects.extend(read_sequences(&pool, &excluded).await?);
+    objects.extend(read_views(&pool, &excluded).await?);
+    objects.extend(read_functions(&pool, &excluded).await?);
+
+    pool.close().await;
+    Ok(objects)
+}
+
+/// Reads the tables with their columns and constraints.
+async fn read_tables(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let tables = query(&format!(
+        "SELECT c.oid::bigint AS table_oid, n.nspname::text AS schema_name,
+                c.relname::text AS table_name,
+                quote_ident(n.nspname) || '.' || quote_ident(c.relname) AS quoted_name
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind IN ('r', 'p')
+              AND NOT c.relispartition
+              AND n.nspname NOT IN {}
+              AND n.nspname NOT LIKE 'pg_temp%'
+              AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype = 'e')
+            ORDER BY n.nspname, c.relname;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut objects = Vec::new();
+    for table in tables {
+        let table_oid: i64 = table.try_get("table_oid")?;
+        let table_name: String = table.try_get("table_name")?;
+        let mut definition = TableDefinition {
+            schema_name: table.try_get("schema_name")?,
+            table_name: table.try_get("quoted_name")?,
+            ..Default::default()
+        };
+
+        let columns = query(
+            "SELECT quote_ident(a.attname) AS column_name,
+                    format_type(a.atttypid, a.atttypmod) AS data_type,
+                    a.attnotnull AS not_null,
+                    a.attidentity::text AS identity,
+                    a.attgenerated::text AS generated,
+                    pg_get_expr(d.adbin, d.adrelid) AS column_default
+                FROM pg_attribute a
+                LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+                WHERE a.attrelid::bigint = $1 AND a.attnum > 0 AND NOT a.attisdropped
+                ORDER BY a.attnum;",
+        )
+        .bind(table_oid)
+        .fetch_all(pool)
+        .await?;
+
+        for column in columns {
+            let name: String = column.try_get("column_name")?;
+            let mut data_type: String = column.try_get("data_type")?;
+            let identity: String = column.try_get("identity")?;
+            let generated: String = column.try_get("generated")?;
+            let mut default: Option<String> = column.try_get("column_default")?;
+
+            match (identity.as_str(), generated.as_str()) {
+                ("a", _) => data_type.push_str(" GENERATED ALWAYS AS IDENTITY"),
+                ("d", _) => data_type.push_str(" GENERATED BY DEFAULT AS IDENTITY"),
+                (_, "s") => {
+                    if let Some(expression) = default.take() {
+                        data_type
+                            .push_str(&format!(" GENERATED ALWAYS AS ({}) STORED", expression));
+                    }
+                }
+                _ => {}
+            }
+
+            // Defaults that use a sequence are set once the sequence exists
+            if let Some(expression) = default.take() {
+                if expression.starts_with("nextval(") {
+                    definition.column_defaults.push((name.clone(), expression));
+                } else {
+                    default = Some(expression);
+                }
+            }
+
+            definition.columns.push(ColumnDefinition {
+                name,
+                data_type,
+                not_null: column.try_get("not_null")?,
+                default,
+            });
+        }
+
+        let constraints = query(
+            "SELECT quote_ident(conname) AS constraint_name,
+                    pg_get_constraintdef(oid) AS definition
+                FROM pg_constraint
+                WHERE conrelid::bigint = $1 AND contype IN ('p', 'u', 'c', 'x', 'f')
+                ORDER BY CASE contype WHEN 'p' THEN 0 WHEN 'u' THEN 1 WHEN 'c' THEN 2
+                                      WHEN 'x' THEN 3 ELSE 4 END, conname;",
+        )
+        .bind(table_oid)
+        .fetch_all(pool)
+        .await?;
+
+        for constraint in constraints {
+            definition.constraints.push((
+                constraint.try_get("constraint_name")?,
+                constraint.try_get("definition")?,
+            ));
+        }
+
+        objects.push(definition.into_object(&table_name));
+    }
+
+    Ok(objects)
+}
+
+/// Reads the indexes that are not created by a constraint.
+async fn read_indexes(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let indexes = query(&format!(
+        "SELECT n.nspname::text AS schema_name, i.relname::text AS index_name,
+                pg_get_indexdef(i.oid) AS definition
+            FROM pg_index x
+            JOIN pg_class i ON i.oid = x.indexrelid
+            JOIN pg_class t ON t.oid = x.indrelid
+            JOIN pg_namespace n ON n.oid = i.relnamespace
+            WHERE t.relkind IN ('r', 'p', 'm')
+              AND n.nspname NOT IN {}
+              AND NOT EXISTS (SELECT 1 FROM pg_constraint c WHERE c.conindid = x.indexrelid)
+              AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = t.oid AND d.deptype = 'e')
+            ORDER BY n.nspname, i.relname;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    indexes
+        .iter()
+        .map(|index| {
+            let definition: String = index.try_get("definition")?;
+            Ok(GeneratedObject {
+                schema_name: index.try_get("schema_name")?,
+                object_type: "index".to_string(),
+                object_name: index.try_get("index_name")?,
+                changes: vec![GeneratedChange {
+                    name: Some("change0".to_string()),
+                    sql: format!("{};", definition),
+                }],
+            })
+        })
+        .collect()
+}
+
+/// Reads the sequences that do not back an identity column.
+async fn read_sequences(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let sequences = query(&format!(
+        "SELECT n.nspname::text AS schema_name, c.relname::text AS sequence_name,
+                quote_ident(n.nspname) || '.' || quote_ident(c.relname) AS quoted_name,
+                format_type(s.seqtypid, NULL) AS data_type,
+                s.seqstart AS start_value, s.seqincrement AS increment_by,
+                s.seqmin AS min_value, s.seqmax AS max_value, s.seqcache AS cache_size,
+                s.seqcycle AS cycle
+            FROM pg_sequence s
+            JOIN pg_class c ON c.oid = s.seqrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname NOT IN {}
+              AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype IN ('i', 'e'))
+            ORDER BY n.nspname, c.relname;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    sequences
+        .iter()
+        .map(|sequence| {
+            let quoted_name: String = sequence.try_get("quoted_name")?;
+            let data_type: String = sequence.try_get("data_type")?;
+            let start_value: i64 = sequence.try_get("start_value")?;
+            let increment_by: i64 = sequence.try_get("increment_by")?;
+            let min_value: i64 = sequence.try_get("min_value")?;
+            let max_value: i64 = sequence.try_get("max_value")?;
+            let cache_size: i64 = sequence.try_get("cache_size")?;
+            let cycle: bool = sequence.try_get("cycle")?;
+            Ok(GeneratedObject {
+                schema_name: sequence.try_get("schema_name")?,
+                object_type: "sequence".to_string(),
+                object_name: sequence.try_get("sequence_name")?,
+                changes: vec![GeneratedChange {
+                    name: Some("change0".to_string()),
+                    sql: format!(
+                        "CREATE SEQUENCE {} AS {} INCREMENT BY {} MINVALUE {} MAXVALUE {} START WITH {} CACHE {}{};",
+                        quoted_name,
+                        data_type,
+                        increment_by,
+                        min_value,
+                        max_value,
+                        start_value,
+                        cache_size,
+                        if cycle { " CYCLE" } else { "" }
+                    ),
+                }],
+            })
+        })
+        .collect()
+}
+
+/// Reads the views and materialized views.
+async fn read_views(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let views = query(&format!(
+        "SELECT n.nspname::text AS schema_name, c.relname::text AS view_name,
+                quote_ident(n.nspname) || '.' || quote_ident(c.relname) AS quoted_name,
+                c.relkind::text AS kind,
+                pg_get_viewdef(c.oid, true) AS definition
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind IN ('v', 'm')
+              AND n.nspname NOT IN {}
+              AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype = 'e')
+            ORDER BY n.nspname, c.relname;",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    views
+        .iter()
+        .map(|view| {
+            let quoted_name: String = view.try_get("quoted_name")?;
+            let kind: String = view.try_get("kind")?;
+            let definition: String = view.try_get("definition")?;
+            let (object_type, create) = if kind == "m" {
+                ("materialized_view", "CREATE MATERIALIZED VIEW")
+            } else {
+                ("view", "CREATE VIEW")
+            };
+            Ok(GeneratedObject {
+                schema_name: view.try_get("schema_name")?,
+                object_type: object_type.to_string(),
+                object_name: view.try_get("view_name")?,
+                changes: vec![GeneratedChange {
+                    name: None,
+                    sql: format!("{} {} AS\n{}", create, quoted_name, definition),
+                }],
+            })
+        })
+        .collect()
+}
+
+/// Reads the functions, with every overload of a name in the same object.
+///
+/// Procedures are skipped with a warning, since the SQL parser cannot read their PostgreSQL
+/// definition back.
+async fn read_functions(
+    pool: &AnyPool,
+    excluded: &str,
+) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let functions = query(&format!(
+        "SELECT n.nspname::text AS schema_name, p.proname::text AS function_name,
+                p.prokind::text AS kind, pg_get_functiondef(p.oid) AS definition
+            FROM pg_proc p
+            JOIN pg_namespace n ON n.oid = p.pronamespace
+            WHERE p.prokind IN ('f', 'p')
+              AND n.nspname NOT IN {}
+              AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = p.oid AND d.deptype = 'e')
+            ORDER BY n.nspname, p.proname, pg_get_function_identity_arguments(p.oid);",
+        excluded
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut objects: Vec<GeneratedObject> = Vec::new();
+    for function in functions {
+        let schema_name: String = function.try_get("schema_name")?;
+        let function_name: String = function.try_get("function_name")?;
+        let kind: String = function.try_get("kind")?;
+        if kind == "p" {
+            log::warn!(
+                "Procedure {}.{} skipped, procedures cannot be generated yet",
+                schema_name,
+                function_name
+            );
+            continue;
+        }
+        let definition: String = function.try_get("definition")?;
+        let change = GeneratedChange {
+            name: None,
+            sql: format!("{};", definition.trim_end()),
+        };
+
+        match objects.last_mut() {
+            Some(last) if last.schema_name == schema_name && last.object_name == function_name => {
+                last.changes.push(change);
+            }
+            _ => objects.push(GeneratedObject {
+                schema_name,
+                object_type: "function".to_string(),
+                object_name: function_name,
+                changes: vec![change],
+            }),
+        }
+    }
+
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excluded_schemas_escape_the_metadata_schema() {
+        assert_eq!(
+            excluded_schemas("oxi'gration"),
+            "('pg_catalog', 'information_schema', 'pg_toast', 'oxi''gration')"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use sqlx::{query, AnyPool, Row};
+use std::error::Error;
+
+use super::{GeneratedChange, GeneratedObject};
+
+/// The schema directory of the objects of a SQLite database.
+const SCHEMA_NAME: &str = "main";
+
+/// Tables created by oxigration in SQLite databases, which have no schema to keep them apart.
+const DEPLOY_LOG_TABLES: &[&str] = &["deploy_log", "deploy_log_config", "deploy_execution"];
+
+/// Reads the tables, indexes and views of a SQLite database from `sqlite_master`.
+///
+/// SQLite cannot add constraints to an existing table, so each table is written as a single
+/// change with the `CREATE TABLE` statement stored by SQLite, which already contains its
+/// constraints. The indexes SQLite creates automatically for constraints have no statement and are
+/// skipped, and triggers are skipped with a warning.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Vec<GeneratedObject>)` with the objects ordered by type and name.
+/// * `Err(Box<dyn Error>)` if the catalog cannot be queried.
+pub async fn read_catalog(connection_string: &str) -> Result<Vec<GeneratedObject>, Box<dyn Error>> {
+    let pool = AnyPool::connect(connection_string).await?;
+
+    let rows = query(
+        "SELECT type, name, sql FROM sqlite_master
+            WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'
+            ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 WHEN 'view' THEN 2 ELSE 3 END,
+                     name;",
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let mut objects = Vec::new();
+    for row in rows {
+        let object_type: String = row.try_get("type")?;
+        let name: String = row.try_get("name")?;
+        let sql: String = row.try_get("sql")?;
+
+        let change_name = match object_type.as_str() {
+            "table" if DEPLOY_LOG_TABLES.contains(&name.as_str()) => continue,
+            "table" | "index" => Some("change0".to_string()),
+            "view" => None,
+            _ => {
+                log::warn!(
+                    "{} {} skipped, it cannot be generated yet",
+                    object_type,
+                    name
+                );
+                continue;
+            }
+        };
+
+        objects.push(GeneratedObject {
+            schema_name: SCHEMA_NAME.to_string(),
+            object_type,
+            object_name: name,
+            changes: vec![GeneratedChange {
+                name: change_name,
+                sql: format!("{};", sql.trim_end_matches(';')),
+            }],
+        });
+    }
+
+    pool.close().await;
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_log::tests::sqlite_database;
+    use crate::generator::write_objects;
+    use crate::source_code::read_source_code;
+    use sqlx::Executor;
+
+    #[tokio::test]
+    async fn test_read_sqlite_catalog() -> Result<(), Box<dyn Error>> {
+        let (dir, connection_string, _) = sqlite_database().await?;
+        let pool = AnyPool::connect(&connection_string).await?;
+        pool.execute(
+            "CREATE TABLE table_a (a_id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+            CREATE TABLE table_b (b_id INTEGER PRIMARY KEY, a_id INTEGER REFERENCES table_a (a_id));
+            CREATE INDEX table_b_a_id ON table_b (a_id);
+            CREATE VIEW view1 AS SELECT a_id, name FROM table_a;
+            CREATE TABLE deploy_log (id INTEGER PRIMARY KEY);",
+        )
+        .await?;
+        pool.close().await;
+
+        let objects = read_catalog(&connection_string).await?;
+        let names: Vec<(&str, &str)> = objects
+            .iter()
+            .map(|o| (o.object_type.as_str(), o.object_name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("table", "table_a"),
+                ("table", "table_b"),
+                ("index", "table_b_a_id"),
+                ("view", "view1")
+            ]
+        );
+
+        let base_dir = dir.path().join("schemas");
+        let base_dir = base_dir.to_str().unwrap();
+        write_objects(base_dir, &objects)?;
+        let source_code = read_source_code(base_dir, None)?;
+        assert!(source_code.contains_key("main.table.table_a.change0"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::dependency_graph::{dependency_edges, DependencyEdge, DependencyReason, SymbolTable};
+use crate::source_code::DatabaseObject;
+
+/// The formats the dependency graph can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT, rendered with `dot -Tsvg`.
+    Dot,
+    /// A Mermaid flowchart, rendered by most code review tools.
+    Mermaid,
+    /// The nodes and edges as JSON, for other tools.
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!(
+                "Unknown graph format '{}', expected dot, mermaid or json",
+                format
+            )),
+        }
+    }
+}
+
+/// Selects the part of the dependency graph to export.
+#[derive(Debug, Clone, Default)]
+pub struct GraphFilter {
+    /// Keeps the changes of this schema directory.
+    pub schema: Option<String>,
+    /// Keeps the changes of this object type directory.
+    pub object_type: Option<String>,
+    /// Keeps the changes of this object or change and every change that depends on them,
+    /// directly or not.
+    pub dependents_of: Option<String>,
+    /// Keeps the changes of this object or change and every change they depend on, directly or
+    /// not.
+    pub dependencies_of: Option<String>,
+}
+
+/// The dependency graph of the changes of the source code.
+///
+/// The nodes are the changes, in execution order, and the edges are the ones used to determine
+/// that order, from a change to the changes that depend on it.
+pub struct DependencyGraph<'a> {
+    object_info: &'a IndexMap<String, DatabaseObject>,
+    nodes: IndexSet<&'a str>,
+    edges: Vec<DependencyEdge<'a>>,
+}
+
+impl<'a> DependencyGraph<'a> {
+    /// Builds the dependency graph of the source code.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_info` - The changes of the source code, in execution order.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result` containing:
+    /// * `Ok(DependencyGraph)` - The graph of every change.
+    /// * `Err(Box<dyn Error>)` - If a dependency is unresolved or ambiguous.
+    pub fn build(
+        object_info: &'a IndexMap<String, DatabaseObject>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(DependencyGraph {
+            object_info,
+            nodes: object_info.keys().map(String::as_str).collect(),
+            edges: dependency_edges(object_info)?,
+        })
+    }
+
+    /// Keeps the changes for which `keep` returns `true`, and the edges between them.
+    pub fn retain(&mut self, keep: impl Fn(&DatabaseObject) -> bool) {
+        let object_info = self.object_info;
+        self.nodes.retain(|key| keep(&object_info[*key]));
+        let nodes = &self.nodes;
+        self.edges
+            .retain(|edge| nodes.contains(edge.dependency) && nodes.contains(edge.dependent));
+    }
+
+    /// Keeps the changes selected by a filter, and the edges between them.
+    ///
+    /// The dependents and dependencies are followed through every change of the graph, before the
+    /// schema and object type filters are applied. When both are given, the changes of both sides
+    /// are kept.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the object or change of `dependents_of` or
+    /// `dependencies_of` does not match exactly one object or change.
+    pub fn select(&mut self, filter: &GraphFilter) -> Result<(), Box<dyn Error>> {
+        let symbols = SymbolTable::new(self.object_info);
+        let mut selected: Option<IndexSet<&str>> = None;
+        for (name, dependents) in [
+            (&filter.dependents_of, true),
+            (&filter.dependencies_of, false),
+        ] {
+            if let Some(name) = name {
+                let start = symbols
+                    .resolve_name(name)?
+                    .into_iter()
+                    .filter(|key| self.nodes.contains(key));
+                selected
+                    .get_or_insert_with(IndexSet::new)
+                    .extend(self.reachable(start, dependents));
+            }
+        }
+
+        let matches = |filter: &Option<String>, value: &str| match filter {
+            Some(filter) => value.eq_ignore_ascii_case(filter),
+            None => true,
+        };
+        self.retain(|obj| {
+            let is_selected = match &selected {
+                Some(selected) => selected.contains(obj.change_name.as_str()),
+                None => true,
+            };
+            is_selected
+                && matches(&filter.schema, &obj.schema_name)
+                && matches(&filter.object_type, &obj.object_type)
+        });
+        Ok(())
+    }
+
+    /// Returns the changes reachable from `start`, following the edges forward to the dependents
+    /// or backward to the dependencies.
+    fn reachable(
+        &self,
+        start: impl Iterator<Item = &'a str>,
+        dependents: bool,
+    ) -> IndexSet<&'a str> {
+        let mut reached: IndexSet<&'a str> = start.collect();
+        let mut next = 0;
+        while let Some(&key) = reached.get_index(next) {
+            for edge in &self.edges {
+                let (from, to) = if dependents {
+                    (edge.dependency, edge.dependent)
+                } else {
+                    (edge.dependent, edge.dependency)
+                };
+                if from == key {
+                    reached.insert(to);
+                }
+            }
+            next += 1;
+        }
+        reached
+    }
+
+    /// Renders the graph in the given format.
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Renders the graph as Graphviz DOT, with a cluster per object.
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph dependencies {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (object, keys) in self.nodes_by_object() {
+            dot.push_str(&format!(
+                "    subgraph \"cluster_{}\" {{\n        label=\"{}\";\n",
+                dot_escape(&object),
+                dot_escape(&object)
+            ));
+            for key in keys {
+                dot.push_str(&format!(
+                    "        \"{}\" [label=\"{}\"];\n",
+                    dot_escape(key),
+                    dot_escape(self.object_info[key].short_change_name())
+                ));
+            }
+            dot.push_str("    }\n");
+        }
+        for (dependency, dependent, label) in self.labeled_edges() {
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\"",
+                dot_escape(dependency),
+                dot_escape(dependent)
+            ));
+            if !label.is_empty() {
+                dot.push_str(&format!(" [label=\"{}\"]", dot_escape(&label)));
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as a Mermaid flowchart, with a subgraph per object.
+    pub fn to_mermaid(&self) -> String {
+        // Keys contain dots, which Mermaid does not accept in node ids
+        let id = |key: &str| format!("n{}", self.nodes.get_index_of(key).unwrap_or_default());
+        let mut mermaid = String::from("flowchart LR\n");
+        for (index, (object, keys)) in self.nodes_by_object().into_iter().enumerate() {
+            mermaid.push_str(&format!(
+                "    subgraph o{} [\"{}\"]\n",
+                index,
+                mermaid_escape(&object)
+            ));
+            for key in keys {
+                mermaid.push_str(&format!(
+                    "        {}[\"{}\"]\n",
+                    id(key),
+                    mermaid_escape(self.object_info[key].short_change_name())
+                ));
+            }
+            mermaid.push_str("    end\n");
+        }
+        for (dependency, dependent, label) in self.labeled_edges() {
+            if label.is_empty() {
+                mermaid.push_str(&format!("    {} --> {}\n", id(dependency), id(dependent)));
+            } else {
+                mermaid.push_str(&format!(
+                    "    {} -->|\"{}\"| {}\n",
+                    id(dependency),
+                    mermaid_escape(&label),
+                    id(dependent)
+                ));
+            }
+        }
+        mermaid
+    }
+
+    /// Renders the graph as JSON, with every edge and its reason.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct JsonNode<'a> {
+            key: &'a str,
+            schema: &'a str,
+            object_type: &'a str,
+            object_name: &'a str,
+            change: &'a str,
+            file: &'a str,
+            line: usize,
+        }
+        #[derive(Serialize)]
+        struct JsonEdge<'a> {
+            from: &'a str,
+            to: &'a str,
+            reason: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            name: Option<&'a str>,
+        }
+        #[derive(Serialize)]
+        struct JsonGraph<'a> {
+            nodes: Vec<JsonNode<'a>>,
+            edges: Vec<JsonEdge<'a>>,
+        }
+
+        let graph = JsonGraph {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|key| {
+                    let obj = &self.object_info[*key];
+                    JsonNode {
+                        key,
+                        schema: &obj.schema_name,
+                        object_type: &obj.object_type,
+                        object_name: &obj.object_name,
+                        change: obj.short_change_name(),
+                        file: &obj.file_path,
+                        line: obj.line,
+                    }
+                })
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|edge| {
+                    let (reason, name) = match &edge.reason {
+                        DependencyReason::PreviousChange => ("previous_change", None),
+                        DependencyReason::Depends(name) => ("depends", Some(name.as_str())),
+                        DependencyReason::Reference(name) => ("reference", Some(name.as_str())),
+                    };
+                    JsonEdge {
+                        from: edge.dependency,
+                        to: edge.dependent,
+                        reason,
+                        name,
+                    }
+                })
+                .collect(),
+        };
+        let mut json = serde_json::to_string_pretty(&graph).unwrap_or_default();
+        json.push('\n');
+        json
+    }
+
+    /// Returns the nodes grouped by `schema.type.object`, in execution order of their first change.
+    fn nodes_by_object(&self) -> IndexMap<String, Vec<&'a str>> {
+        let mut objects: IndexMap<String, Vec<&'a str>> = IndexMap::new();
+        for key in &self.nodes {
+            let obj = &self.object_info[*key];
+            objects
+                .entry(format!(
+                    "{}.{}.{}",
+                    obj.schema_name, obj.object_type, obj.object_name
+                ))
+                .or_default()
+                .push(key);
+        }
+        objects
+    }
+
+    /// Returns each edge once with the reasons of its declared dependencies and references. The
+    /// dependencies on the previous changes of a file have no label.
+    fn labeled_edges(&self) -> Vec<(&'a str, &'a str, String)> {
+        let mut labels: IndexMap<(&'a str, &'a str), Vec<String>> = IndexMap::new();
+        for edge in &self.edges {
+            let reasons = labels.entry((edge.dependency, edge.dependent)).or_default();
+            if edge.reason != DependencyReason::PreviousChange {
+                reasons.push(edge.reason.to_string());
+            }
+        }
+        labels
+            .into_iter()
+            .map(|((dependency, dependent), reasons)| (dependency, dependent, reasons.join(", ")))
+            .collect()
+    }
+}
+
+/// Escapes a string for a quoted DOT identifier.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes a string for a quoted Mermaid label.
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_code::read_source_code;
+
+    fn baseline() -> IndexMap<String, DatabaseObject> {
+        read_source_code("tests/schemas/baseline/", None).unwrap()
+    }
+
+    #[test]
+    fn test_dependents_and_dependencies_are_transitive() {
+        let source_code = baseline();
+
+        let mut graph = DependencyGraph::build(&source_code).unwrap();
+        graph
+            .select(&GraphFilter {
+                dependents_of: Some("baseline.table.table_a.change0".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            graph.nodes.iter().copied().collect::<Vec<_>>(),
+            vec![
+                "baseline.table.table_a.change0",
+                "baseline.table.table_a.change1",
+                "baseline.table.table_b_with_fk.change2",
+            ]
+        );
+
+        let mut graph = DependencyGraph::build(&source_code).unwrap();
+        graph
+            .select(&GraphFilter {
+                dependencies_of: Some("table_b_with_fk.change2".to_string()),
+                object_type: Some("table".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(graph.nodes.len(), 5);
+        assert!(graph.nodes.contains("baseline.table.table_a.change1"));
+        assert!(!graph
+            .nodes
+            .contains("baseline.table.table_a_multicol_pk.change0"));
+
+        let mut graph = DependencyGraph::build(&source_code).unwrap();
+        let error = graph
+            .select(&GraphFilter {
+                dependents_of: Some("change0".to_string()),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("'change0' is ambiguous"));
+    }
+
+    #[test]
+    fn test_graph_formats() {
+        let source_code = baseline();
+        let mut graph = DependencyGraph::build(&source_code).unwrap();
+        graph
+            .select(&GraphFilter {
+                schema: Some("baseline".to_string()),
+                dependencies_of: Some("view1".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let dot = graph.render(GraphFormat::Dot);
+        assert!(dot.starts_with("digraph dependencies {"));
+        assert!(dot.contains("subgraph \"cluster_baseline.view.view1\""));
+        assert!(dot.contains(
+            "\"baseline.table.metadata_test_table.change0\" -> \"baseline.view.view1.root0\" [label=\"reference to 'metadata_test_table'\"];"
+        ));
+
+        let mermaid = graph.render(GraphFormat::Mermaid);
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("    subgraph o0 [\"baseline.table.metadata_test_table\"]\n"));
+        assert!(mermaid.contains("    n0 -->|\"reference to 'metadata_test_table'\"| n1\n"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&graph.render(GraphFormat::Json)).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 2);
+        assert_eq!(json["nodes"][1]["key"], "baseline.view.view1.root0");
+        assert_eq!(json["edges"][0]["reason"], "reference");
+        assert_eq!(json["edges"][0]["name"], "metadata_test_table");
+
+        assert_eq!("Mermaid".parse(), Ok(GraphFormat::Mermaid));
+        assert!("svg".parse::<GraphFormat>().is_err());
+    }
+}
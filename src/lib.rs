@@ -1,58 +1,96 @@
+mod changeset;
+mod dependency_graph;
 mod deploy_log;
+mod deployer;
+mod environment;
+mod generator;
+mod graph;
+mod lock;
+mod rollback;
 mod source_code;
+mod status;
 mod utils;
 
-use deploy_log::{init_deploy_log, read_deploy_log};
-use log::{error, info};
-use source_code::read_source_code;
+use changeset::{compute_changeset, ChangeSet};
+use deploy_log::{
+    check_metadata_version, deploy_log_exists, deploy_log_outdated, finish_deploy_execution,
+    init_deploy_log, metadata_context, read_config_value, read_deploy_log,
+    read_last_deploy_execution, start_deploy_execution, upgrade_deploy_log, DeployExecutionStatus,
+};
+use deployer::{apply_changeset, rollback_to};
+use environment::{load_environment, Environment};
+use generator::generate_source_code;
+use graph::DependencyGraph;
+pub use graph::{GraphFilter, GraphFormat};
+use indexmap::{IndexMap, IndexSet};
+use lock::{acquire_deploy_lock, force_unlock, lock_holder, DeployLock, UPGRADE_LOCK_HOLDER};
+use log::{debug, error, info};
+use source_code::{read_source_code, DatabaseObject};
 use sqlx::{query_scalar, AnyPool};
+use status::Status;
 use std::env;
+use std::fs;
 use std::path::Path;
+use std::time::Duration;
+use utils::{format_query_with_schema, MetadataContext};
 
-/// Performs pre-migration checks to ensure the base directory exists, the target database is reachable,
-/// the environment variable `ENV` is set correctly, the target database matches the environment, and
-/// rollback is possible by verifying the existence of the deploy log in the database.
+/// Performs pre-migration checks to ensure the environment is declared in `oxigration.toml`, the
+/// target database is reachable, the base directory exists, the target database matches the
+/// environment, and rollback is possible by verifying the existence of the deploy log in the database.
 ///
 /// # Arguments
 ///
 /// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
-/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `environment_name` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `is_init` - Whether the deploy log is being initialized, which only requires the target
+///   database to be reachable.
 ///
 /// # Returns
 ///
 /// This function returns a `Result`:
-/// * `Ok(())` if all checks pass.
+/// * `Ok((Environment, MetadataContext))` with the loaded environment and the kind and metadata
+///   schema of its target database if all checks pass.
 /// * `Err(Box<dyn std::error::Error>)` if any check fails.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The base directory does not exist.
+/// * The environment is not declared in `oxigration.toml`, or its connection string cannot be resolved.
 /// * The target database is not reachable.
-/// * The environment variable `ENV` is not set correctly.
+/// * The base directory does not exist.
 /// * The target database does not match the environment.
 /// * Rollback is not possible because the deploy log does not exist in the database.
 async fn environment_checks(
     base_dir: &str,
-    connection_string: &str,
+    environment_name: &str,
     is_init: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(Environment, MetadataContext), Box<dyn std::error::Error>> {
+    let environment = load_environment(base_dir, environment_name)?;
+    info!("Environment {} loaded", environment.name);
+
     // The `install_default_drivers` function is typically used to install the default SQLx drivers for database connections.
     sqlx::any::install_default_drivers();
 
     // Check if the target DB is reachable
-    let pool = AnyPool::connect(connection_string).await?;
-    let db_reachable: bool = query_scalar("SELECT TRUE;").fetch_one(&pool).await?;
-    if !db_reachable {
+    let pool = AnyPool::connect(&environment.connection).await?;
+    let db_reachable: i64 = query_scalar("SELECT 1;").fetch_one(&pool).await?;
+    if db_reachable != 1 {
         error!("Target database is not reachable");
         return Err("Target database is not reachable".into());
     } else {
         info!("Target database is reachable");
+    }
 
-        // If the deploy log is being initialized, return Ok as there is no need to check anything else
-        if is_init {
-            return Ok(());
-        }
+    let metadata = metadata_context(
+        &pool,
+        &environment.connection,
+        environment.metadata_schema(),
+    )
+    .await?;
+
+    // If the deploy log is being initialized, return Ok as there is no need to check anything else
+    if is_init {
+        return Ok((environment, metadata));
     }
 
     // Check if the base_dir exists
@@ -63,42 +101,29 @@ async fn environment_checks(
         info!("Base directory exists: {}", base_dir);
     }
 
-    // Check if the environment variables are set (DEV, TEST, PROD)
-    let env = env::var("ENV").unwrap_or_else(|_| "DEV".to_string());
-    if !["DEV", "TEST", "PROD", "STAGE"].contains(&env.as_str()) {
-        error!("Environment variable ENV is not set correctly");
-        return Err("Environment variable ENV is not set correctly".into());
-    } else {
-        info!("Environment variable ENV is set to: {}", env);
-    }
-
-    // Check if the target DB is the correct one (DEV, TEST, PROD)
-    let db_env: String =
-        query_scalar("SELECT value FROM oxigration.deploy_log_config WHERE key = 'env';")
-            .fetch_one(&pool)
-            .await?;
+    // Check if the target DB is the one of the environment
+    let db_env: String = query_scalar(&format_query_with_schema(
+        "SELECT value FROM {schema_prefix}deploy_log_config WHERE \"key\" = 'env';",
+        &metadata,
+    ))
+    .fetch_one(&pool)
+    .await?;
 
-    if db_env != env {
+    if db_env != environment.name {
         error!(
-            "Target database environment ({}) does not match the environment variable ENV ({})",
-            db_env, env
-        );
-        return Err(
-            "Target database environment does not match the environment variable ENV".into(),
+            "Target database environment ({}) does not match the environment ({})",
+            db_env, environment.name
         );
+        return Err("Target database environment does not match the environment".into());
     } else {
         info!(
-            "Target database environment matches the environment variable ENV: {}",
-            env
+            "Target database environment matches the environment: {}",
+            environment.name
         );
     }
 
     // Check if the deploy_log table exists
-    let table_exists: bool = query_scalar(
-        "SELECT EXISTS (SELECT table_name FROM information_schema.tables WHERE table_schema = 'oxigration' AND table_name = 'deploy_log');"
-    )
-    .fetch_one(&pool)
-    .await?;
+    let table_exists = deploy_log_exists(&pool, &metadata).await?;
 
     if !table_exists {
         error!("Rollback is not possible, deploy log does not exist in the database");
@@ -106,29 +131,33 @@ async fn environment_checks(
     }
 
     // Check if the deploy_log table has entries
-    let log_has_entries: bool =
-        query_scalar("SELECT EXISTS (SELECT 1 FROM oxigration.deploy_log LIMIT 1);")
-            .fetch_one(&pool)
-            .await?;
+    let log_entries: i64 = query_scalar(&format_query_with_schema(
+        "SELECT COUNT(*) FROM {schema_prefix}deploy_log;",
+        &metadata,
+    ))
+    .fetch_one(&pool)
+    .await?;
 
-    if !log_has_entries {
+    if log_entries == 0 {
         error!("Rollback is not possible, deploy log does not exist in the database");
         // return Err("Rollback is not possible, deploy log does not exist in the database".into());
     } else {
         info!("Rollback is possible, deploy log exists in the database");
     }
 
-    Ok(())
+    Ok((environment, metadata))
 }
 
 /// This function initializes the deploy log and the configuration settings in the target database.
 ///
 /// It performs the following steps:
-/// 1. Connects to the target database using the provided connection string.
-/// 2. If the database supports schemas, it creates the `oxigration` schema if it does not already exist.
+/// 1. Connects to the target database using the connection string of the environment.
+/// 2. If the database supports schemas, it creates the metadata schema of the environment
+///    (`oxigration` by default) if it does not already exist.
 /// 3. Creates the `deploy_log` table if it does not already exist. This table is used to keep track of all the changes that have been applied to the database.
 /// 4. Creates the `deploy_log_config` table if it does not already exist. This table is used to store configuration settings related to the deployment process.
 /// 5. Inserts initial configuration settings into the `deploy_log_config` table if they do not already exist.
+/// 6. Upgrades the tables created by an older version of oxigration under the deployment lock.
 ///
 /// The `deploy_log` table is crucial for tracking which changes have been applied to the database, ensuring that changes are not reapplied, and enabling rollback functionality.
 /// The `deploy_log_config` table stores settings that can influence the deployment process, such as environment-specific configurations.
@@ -136,7 +165,8 @@ async fn environment_checks(
 /// # Arguments
 ///
 /// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
-/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `lock_timeout` - How long to wait for the deployment lock if a migration holds it.
 ///
 /// # Returns
 ///
@@ -149,14 +179,48 @@ async fn environment_checks(
 /// This function will return an error if:
 /// * There is an issue connecting to the database.
 /// * There is an error executing the SQL statements to create the schema, tables, or insert the configuration settings.
-/// * The base directory does not exist.
+/// * The environment is not declared in the `oxigration.toml` file of the base directory.
 /// * The target database is not reachable.
-/// * The environment variable `ENV` is not set correctly.
-/// * The target database does not match the environment.
-/// * Rollback is not possible because the deploy log does not exist in the database.
-pub async fn init(connection_string: &str) -> Result<(), Box<dyn std::error::Error>> {
-    environment_checks("", connection_string, true).await?;
-    init_deploy_log(connection_string).await?;
+/// * The deploy log tables cannot be upgraded to the running version of oxigration.
+pub async fn init(
+    base_dir: &str,
+    environment: &str,
+    lock_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (environment, metadata) = environment_checks(base_dir, environment, true).await?;
+    init_deploy_log(&environment.connection, &metadata, &environment.name).await?;
+    upgrade_metadata(&environment, &metadata, lock_timeout).await
+}
+
+/// Upgrades the deploy log tables created by an older version of oxigration under the deployment
+/// lock, so that concurrent runs do not upgrade them twice. The lock is only taken when the deploy
+/// log is outdated, and is held for the upgrade only, since the deploy executions cannot be
+/// recorded before it.
+async fn upgrade_metadata(
+    environment: &Environment,
+    metadata: &MetadataContext,
+    lock_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connection_string = environment.connection.as_str();
+    if !deploy_log_outdated(connection_string, metadata).await? {
+        return Ok(());
+    }
+
+    let lock = acquire_deploy_lock(
+        connection_string,
+        metadata,
+        UPGRADE_LOCK_HOLDER,
+        lock_timeout,
+    )
+    .await?;
+    let result = upgrade_deploy_log(connection_string, metadata).await;
+    release_lock(lock).await;
+    if result? {
+        info!(
+            "Deploy log upgraded to oxigration {}",
+            env!("CARGO_PKG_VERSION")
+        );
+    }
     Ok(())
 }
 
@@ -164,22 +228,30 @@ pub async fn init(connection_string: &str) -> Result<(), Box<dyn std::error::Err
 ///
 /// This function performs the following steps:
 /// 1. Pre-migration checks:
-///    - Verifies if the base directory exists.
+///    - Loads the environment from the `oxigration.toml` file of the base directory.
 ///    - Checks if the target database is reachable.
-///    - Ensures the environment variable `ENV` is set correctly (DEV, TEST, PROD).
-///    - Confirms that the target database matches the environment.
+///    - Verifies if the base directory exists.
+///    - Confirms that the target database was initialized for the environment.
 ///    - Checks if rollback is possible by verifying the existence of the deploy log in the database.
-/// 2. Reads and processes the desired schema and changes from the source code in the base directory.
-/// 3. Reads changes from the deploy log in the target database.
-/// 4. Computes the changeset between the source code and the deploy log.
-/// 5. Applies changes to the target database.
-/// 6. Updates the deploy log to reflect the new state of the environment.
-/// 7. Disconnects from the database.
+///    - Upgrades the deploy log tables created by an older version of oxigration, under the
+///      deployment lock.
+/// 2. Reads and processes the desired schema and changes from the source code in the base directory,
+///    keeping the schemas and the changes deployed to the environment.
+/// 3. Takes the exclusive deployment lock of the target database, so that concurrent migrations
+///    do not interleave their changes.
+/// 4. Reads changes from the deploy log in the target database.
+/// 5. Computes the changeset between the source code and the deploy log.
+/// 6. Applies changes to the target database, updating the deploy log in the same transaction to
+///    reflect the new state of the environment, then releases the lock.
+///
+/// Steps 3 to 6 are recorded as a row of the `deploy_execution` table, opened as `RUNNING` and
+/// closed as `SUCCEEDED` or `FAILED` with the error in its `reason` column.
 ///
 /// # Arguments
 ///
 /// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
-/// * `_connection_string` - A string slice that holds the connection string to the target database.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `lock_timeout` - How long to wait for the deployment lock if another migration holds it.
 ///
 /// # Returns
 ///
@@ -191,49 +263,551 @@ pub async fn init(connection_string: &str) -> Result<(), Box<dyn std::error::Err
 ///
 /// This function will return an error if:
 /// * The pre-migration checks fails.
+/// * The deployment lock is still held by another deploy execution after `lock_timeout`.
 /// * Any other error occurs during the migration process.
 pub async fn migrate(
     base_dir: &str,
-    connection_string: &str,
+    environment: &str,
+    lock_timeout: Duration,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Pre-migration checks
-    environment_checks(base_dir, connection_string, false).await?;
+    let (environment, metadata) = environment_checks(base_dir, environment, false).await?;
+    let connection_string = environment.connection.as_str();
+    upgrade_metadata(&environment, &metadata, lock_timeout).await?;
 
     // Step 0: Read and process the desired schema and changes from the source code in base_dir.
     // This step involves parsing the SQL files, processing them, and storing the information in memory. It parses the SQL inside each file and builds a graph representation of each database object, its modifications over time, and other dependencies.
     // The information from the AST tree is used to build a graph where all the other database objects that have a dependency on that object are stated with a relationship.
     // TODO: With table CREATE statements, it rewrites the initial schema based on all the ALTERS that the table might have along all the files, creating a new CREATE statement that includes all the changes.
-    let _reference_source_code = read_source_code(base_dir)?;
+    let reference_source_code = read_source_code(base_dir, Some(&environment))?;
+
+    // Every run is recorded in the deploy_execution table, with the physical schemas it deploys to,
+    // and the deploy log rows it applies are linked to it through deploy_execution_id.
+    let execution_id = start_deploy_execution(
+        connection_string,
+        &metadata,
+        &requester(),
+        &executor(),
+        &physical_schemas(&environment, &reference_source_code),
+    )
+    .await?;
+    info!("Deploy execution {} started", execution_id);
+
+    let result = deploy(
+        &environment,
+        &metadata,
+        &reference_source_code,
+        execution_id,
+        lock_timeout,
+    )
+    .await;
+    if let Ok(applied) = &result {
+        info!("{} changes applied to the target database", applied);
+    }
+    finish_execution(connection_string, &metadata, execution_id, &result).await?;
+
+    result.map(|_| ())
+}
+
+/// Returns the comma-separated physical schemas of the source code recorded in the `schema`
+/// column of a deploy execution.
+fn physical_schemas(
+    environment: &Environment,
+    reference_source_code: &IndexMap<String, DatabaseObject>,
+) -> String {
+    let schemas: IndexSet<&str> = reference_source_code
+        .values()
+        .map(|object| environment.physical_schema(&object.schema_name))
+        .collect();
+    schemas.into_iter().collect::<Vec<_>>().join(",")
+}
+
+/// Closes a deploy execution as `SUCCEEDED`, or as `FAILED` with the error of its result.
+async fn finish_execution(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    execution_id: i64,
+    result: &Result<usize, Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match result {
+        Ok(_) => {
+            finish_deploy_execution(
+                connection_string,
+                metadata,
+                execution_id,
+                DeployExecutionStatus::Succeeded,
+                None,
+            )
+            .await
+        }
+        Err(e) => {
+            error!("Deploy execution {} failed: {}", execution_id, e);
+            finish_deploy_execution(
+                connection_string,
+                metadata,
+                execution_id,
+                DeployExecutionStatus::Failed,
+                Some(&e.to_string()),
+            )
+            .await
+        }
+    }
+}
+
+/// Computes and applies the changeset of a deploy execution under the deployment lock, returning
+/// the number of changes applied.
+async fn deploy(
+    environment: &Environment,
+    metadata: &MetadataContext,
+    reference_source_code: &IndexMap<String, DatabaseObject>,
+    execution_id: i64,
+    lock_timeout: Duration,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let lock = acquire_deploy_lock(
+        &environment.connection,
+        metadata,
+        execution_id,
+        lock_timeout,
+    )
+    .await?;
+    let result = deploy_changes(environment, metadata, reference_source_code, execution_id).await;
+    release_lock(lock).await;
+    result
+}
+
+/// Releases the deployment lock taken for a deploy execution. A lock that cannot be released is
+/// only logged, so that the caller returns the result of the execution, not the release error.
+async fn release_lock(lock: DeployLock) {
+    if let Err(e) = lock.release().await {
+        error!(
+            "Failed to release the deployment lock, release it with `oxigration unlock --force`: {}",
+            e
+        );
+    }
+}
+
+/// Computes and applies the changeset of a deploy execution, returning the number of changes
+/// applied.
+async fn deploy_changes(
+    environment: &Environment,
+    metadata: &MetadataContext,
+    reference_source_code: &IndexMap<String, DatabaseObject>,
+    execution_id: i64,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let connection_string = environment.connection.as_str();
 
     // Step 1: Read changes from the deploy log in the target database
     // This step involves reading the deploy log to understand the current state of the environment.
-    let _deploy_log = read_deploy_log(connection_string).await?;
+    let deploy_log = environment_changes(
+        read_deploy_log(connection_string, metadata).await?,
+        environment,
+    );
 
     // Step 2: Compute the changeset between the source code and the deploy log
     // This step compares the changes in the source code with the entries in the deploy log.
-    // let changeset = compute_changeset(&_reference_source_code, &deploy_log)?;
+    let changeset = compute_changeset(reference_source_code, &deploy_log)?;
+    if changeset.is_empty() {
+        info!("The target database is up to date, there are no changes to deploy");
+        return Ok(0);
+    }
+    for change in changeset.pending() {
+        debug!(
+            "Pending change {} ({}): {}",
+            change.key,
+            change.action,
+            change.statements()?.join("\n")
+        );
+    }
 
-    // Step 3: Apply changes to the target database
-    // This step involves executing the necessary SQL commands or other database modifications.
-    // apply_changes_to_db(&changeset, _connection_string).await?;
+    // Step 3: Apply changes to the target database and the deploy log
+    // Each change is executed together with the update of its deploy log entry in a single
+    // transaction, so the deploy log reflects the state of the environment, except for the DDL
+    // statements MySQL commits before a change fails.
+    apply_changeset(
+        connection_string,
+        metadata,
+        &changeset,
+        Some(execution_id),
+        &environment.schema_mapping,
+        environment.sql_dialect(),
+    )
+    .await
+}
 
-    // Step 4: Apply changes to the deploy log
-    // After successfully applying the changes, update the deploy log to reflect the new state of the environment.
-    // update_deploy_log(&changeset, _connection_string).await?;
+/// Returns who requested the deployment, from the `OXIGRATION_REQUESTER` environment variable or
+/// the current user.
+fn requester() -> String {
+    env::var("OXIGRATION_REQUESTER")
+        .or_else(|_| env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
 
-    // Step 5: Disconnect from the DB
-    // Ensure that the database connection is properly closed.
-    // disconnect_from_db(_connection_string).await?;
+/// Returns the `user@host` executing the deployment.
+fn executor() -> String {
+    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    let host = env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "localhost".to_string());
+    format!("{}@{}", user, host)
+}
 
-    Ok(())
+/// Keeps the deploy log entries of the environment: the changes of its schemas whose `includeEnvs`
+/// and `excludeEnvs` attributes select it. The changes of other schemas are neither deployed nor
+/// reported as removed. The source code is filtered the same way when it is read.
+fn environment_changes(
+    objects: IndexMap<String, DatabaseObject>,
+    environment: &Environment,
+) -> IndexMap<String, DatabaseObject> {
+    objects
+        .into_iter()
+        .filter(|(_, object)| {
+            environment.allows_schema(&object.schema_name)
+                && environment.includes_change(&object._properties)
+        })
+        .collect()
+}
+
+/// Rolls back the target database to the state it had after a deploy execution.
+///
+/// Every change of the environment applied by a later deploy execution is undone with the rollback
+/// recorded in the deploy log, and its deploy log row is deleted in the same transaction. The
+/// changes are undone in the reverse execution order of the source code, so a change is undone
+/// before the changes it depends on, and the changes no longer in the source code are undone
+/// first. The changes of the schemas and `includeEnvs`/`excludeEnvs` attributes that do not select
+/// the environment are kept, as `migrate` does.
+///
+/// Like `migrate`, the rollback is recorded as a row of the `deploy_execution` table and runs under
+/// the exclusive deployment lock of the target database.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `deploy_execution_id` - The id of the last deploy execution to keep.
+/// * `lock_timeout` - How long to wait for the deployment lock if a migration holds it.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(())` if the rollback is successful.
+/// * `Err(Box<dyn std::error::Error>)` if any error occurs during the rollback.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The pre-migration checks fails.
+/// * The deploy log tables were created by an older version of oxigration and not upgraded yet.
+/// * The deployment lock is still held by another deploy execution after `lock_timeout`.
+/// * A change to roll back has no rollback in the deploy log, such as a redeployed view.
+/// * A rollback fails to execute.
+pub async fn rollback(
+    base_dir: &str,
+    environment: &str,
+    deploy_execution_id: i64,
+    lock_timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (environment, metadata) = environment_checks(base_dir, environment, false).await?;
+    check_metadata_version(&environment.connection, &metadata).await?;
+    let connection_string = environment.connection.as_str();
+
+    // The source code orders the changes to roll back
+    let reference_source_code = read_source_code(base_dir, Some(&environment))?;
+
+    let execution_id = start_deploy_execution(
+        connection_string,
+        &metadata,
+        &requester(),
+        &executor(),
+        &physical_schemas(&environment, &reference_source_code),
+    )
+    .await?;
+    info!(
+        "Deploy execution {} started to roll back to deploy execution {}",
+        execution_id, deploy_execution_id
+    );
+
+    let result = roll_back(
+        &environment,
+        &metadata,
+        &reference_source_code,
+        deploy_execution_id,
+        execution_id,
+        lock_timeout,
+    )
+    .await;
+    if let Ok(rolled_back) = &result {
+        info!(
+            "{} changes rolled back to deploy execution {}",
+            rolled_back, deploy_execution_id
+        );
+    }
+    finish_execution(connection_string, &metadata, execution_id, &result).await?;
+
+    result.map(|_| ())
+}
+
+/// Rolls back the changes applied after a deploy execution under the deployment lock, returning
+/// the number of changes rolled back.
+async fn roll_back(
+    environment: &Environment,
+    metadata: &MetadataContext,
+    reference_source_code: &IndexMap<String, DatabaseObject>,
+    deploy_execution_id: i64,
+    execution_id: i64,
+    lock_timeout: Duration,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let lock = acquire_deploy_lock(
+        &environment.connection,
+        metadata,
+        execution_id,
+        lock_timeout,
+    )
+    .await?;
+    let result = roll_back_changes(
+        environment,
+        metadata,
+        reference_source_code,
+        deploy_execution_id,
+    )
+    .await;
+    release_lock(lock).await;
+    result
+}
+
+/// Rolls back the changes of the environment applied after a deploy execution, returning the
+/// number of changes rolled back.
+async fn roll_back_changes(
+    environment: &Environment,
+    metadata: &MetadataContext,
+    reference_source_code: &IndexMap<String, DatabaseObject>,
+    deploy_execution_id: i64,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let deploy_log = environment_changes(
+        read_deploy_log(&environment.connection, metadata).await?,
+        environment,
+    );
+    rollback_to(
+        &environment.connection,
+        metadata,
+        &deploy_log,
+        reference_source_code,
+        deploy_execution_id,
+        &environment.schema_mapping,
+        environment.sql_dialect(),
+    )
+    .await
+}
+
+/// Releases the deployment lock of the target database.
+///
+/// A lock held by a running migration is released by the database when the migration ends or its
+/// session dies, except in SQLite where a migration that died leaves its lock behind. Such stale
+/// locks are released with `force`, which also ends the session holding the lock and marks its
+/// deploy execution as `FAILED`.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `force` - Whether to release the lock even though a deploy execution holds it.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with a message describing the released lock.
+/// * `Err(Box<dyn std::error::Error>)` if the lock is held and `force` is not set, or it cannot be
+///   released.
+pub async fn unlock(
+    base_dir: &str,
+    environment: &str,
+    force: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (environment, metadata) = environment_checks(base_dir, environment, false).await?;
+
+    if !force {
+        return match lock_holder(&environment.connection, &metadata).await? {
+            Some(id) => Err(format!(
+                "The deployment lock is held by deploy execution #{}, use --force to release it",
+                id
+            )
+            .into()),
+            None => Ok("The deployment lock is not held".to_string()),
+        };
+    }
+
+    Ok(
+        match force_unlock(&environment.connection, &metadata).await? {
+            Some(id) => format!("Deployment lock of deploy execution #{} released", id),
+            None => "Deployment lock released".to_string(),
+        },
+    )
+}
+
+/// Computes the changes that `migrate` would apply to the target database, without executing them.
+///
+/// This function performs the same pre-migration checks and reads the same source code and deploy
+/// log as `migrate`, then renders the ordered changeset as a SQL script. Each change is listed with
+/// its action (deploy, redeploy, drop or error) followed by the exact SQL that would be executed,
+/// so the script can be reviewed before migrating sensitive environments.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+/// * `output` - An optional path of a file where the script is also written.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with the rendered script.
+/// * `Err(Box<dyn std::error::Error>)` if any error occurs while computing the plan.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The pre-migration checks fails.
+/// * The deploy log tables were created by an older version of oxigration and not upgraded yet.
+/// * The source code or the deploy log cannot be read.
+/// * The script cannot be written to the output file.
+pub async fn plan(
+    base_dir: &str,
+    environment: &str,
+    output: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (environment, metadata) = environment_checks(base_dir, environment, false).await?;
+    check_metadata_version(&environment.connection, &metadata).await?;
+
+    let reference_source_code = read_source_code(base_dir, Some(&environment))?;
+    let deploy_log = environment_changes(
+        read_deploy_log(&environment.connection, &metadata).await?,
+        &environment,
+    );
+
+    // Changes that cannot be applied are part of the plan instead of failing it
+    let changeset = ChangeSet::compute(&reference_source_code, &deploy_log);
+    let script = changeset.to_script(&environment.schema_mapping, environment.sql_dialect());
+
+    if let Some(output) = output {
+        fs::write(output, &script)?;
+        info!("Plan written to {}", output);
+    }
+
+    Ok(script)
+}
+
+/// Exports the dependency graph of the source code, which `migrate` uses to order the changes.
+///
+/// The nodes are the changes and the edges go from a change to the changes that depend on it,
+/// labeled with the `depends` attribute or the reference that created them. The graph can be
+/// restricted to a schema, an object type, or the changes that an object depends on or that
+/// depend on it, to review what a change to that object ripples into. Nothing is read from the
+/// target database.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
+/// * `environment` - The optional name of the environment in the `oxigration.toml` file of the base
+///   directory, whose dialect, variables and schemas are used to read the source code.
+/// * `format` - The format of the graph.
+/// * `filter` - The part of the graph to export.
+/// * `output` - An optional path of a file where the graph is also written.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with the rendered graph.
+/// * `Err(Box<dyn std::error::Error>)` if any error occurs while building the graph.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The environment cannot be loaded.
+/// * The source code cannot be read or ordered.
+/// * The object of the filter does not match exactly one object or change.
+/// * The graph cannot be written to the output file.
+pub fn graph(
+    base_dir: &str,
+    environment: Option<&str>,
+    format: GraphFormat,
+    filter: &GraphFilter,
+    output: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let environment = environment
+        .map(|environment| load_environment(base_dir, environment))
+        .transpose()?;
+    let source_code = read_source_code(base_dir, environment.as_ref())?;
+
+    let mut graph = DependencyGraph::build(&source_code)?;
+    graph.select(filter)?;
+    let rendered = graph.render(format);
+
+    if let Some(output) = output {
+        fs::write(output, &rendered)?;
+        info!("Graph written to {}", output);
+    }
+
+    Ok(rendered)
+}
+
+/// Summarizes what is deployed in the target database compared with the source code.
+///
+/// This function compares the source code with the deploy log, like `migrate` does, and counts
+/// per schema and object type how many changes are applied, pending, modified or orphaned (in
+/// the deploy log but no longer in the source code). The report also includes the last row of the
+/// `deploy_execution` table and the `last_version` setting of the `deploy_log_config` table.
+///
+/// # Arguments
+///
+/// * `base_dir` - A string slice that holds the path to the base directory containing the source code.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with the rendered status report.
+/// * `Err(Box<dyn std::error::Error>)` if any error occurs while reading the status.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * The pre-migration checks fails.
+/// * The deploy log tables were created by an older version of oxigration and not upgraded yet.
+/// * The source code or the deploy log tables cannot be read.
+pub async fn status(
+    base_dir: &str,
+    environment: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (environment, metadata) = environment_checks(base_dir, environment, false).await?;
+    check_metadata_version(&environment.connection, &metadata).await?;
+    let connection_string = environment.connection.as_str();
+
+    let reference_source_code = read_source_code(base_dir, Some(&environment))?;
+    let deploy_log = environment_changes(
+        read_deploy_log(connection_string, &metadata).await?,
+        &environment,
+    );
+    let changeset = ChangeSet::compute(&reference_source_code, &deploy_log);
+
+    let last_execution = read_last_deploy_execution(connection_string, &metadata).await?;
+    let last_version = read_config_value(connection_string, &metadata, "last_version").await?;
+
+    Ok(Status::compute(&changeset, last_execution, last_version).to_string())
 }
 
 /// This function generates the source code for the schema from the target database and stores it in the specified base directory.
 ///
+/// Every object of the target database is written into the `schema/object_type/object_name.sql`
+/// layout that `read_source_code` expects. Tables are split into `//// CHANGE` blocks, one for the
+/// `CREATE TABLE` statement and one per constraint, so that legacy databases can be onboarded and
+/// evolved with new changes.
+///
 /// # Arguments
 ///
 /// * `base_dir` - A string slice that holds the path to the base directory where the generated source code will be stored.
-/// * `_connection_string` - A string slice that holds the connection string to the target database.
+/// * `environment` - The name of the environment in the `oxigration.toml` file of the base directory.
 ///
 /// # Returns
 ///
@@ -247,13 +821,14 @@ pub async fn migrate(
 /// 1. Read the schema from the target database.
 /// 2. Generate the source code for the schema.
 /// 3. Store the generated source code in the specified base directory.
-pub async fn generate(
-    base_dir: &str,
-    connection_string: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Read the schema from the target database
-    // Generate the source code for the schema
-    // Store the source code in the base_dir
-    environment_checks(base_dir, connection_string, false).await?;
+pub async fn generate(base_dir: &str, environment: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Generating from a legacy database only requires it to be reachable, it does not need to
+    // have a deploy log yet
+    let (environment, metadata) = environment_checks(base_dir, environment, true).await?;
+
+    // Read the schema from the target database, generate the source code for the schema and
+    // store it in the base_dir
+    let written = generate_source_code(base_dir, &environment.connection, &metadata.schema).await?;
+    info!("{} files generated in {}", written, base_dir);
     Ok(())
 }
@@ -0,0 +1,404 @@
+use sha2::{Digest, Sha256};
+use sqlx::any::AnyPoolOptions;
+use sqlx::{query, query_scalar, AnyPool};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crate::deploy_log::{
+    finish_deploy_execution, read_config_value, read_deploy_execution, DeployExecutionStatus,
+};
+use crate::utils::{format_query_with_schema, DatabaseKind, MetadataContext};
+
+/// The `deploy_log_config` key recording the deploy execution that holds the lock.
+const LOCK_KEY: &str = "deploy_lock";
+
+/// The deploy execution id recorded as the holder of the lock taken to upgrade the deploy log
+/// tables, which happens before the deploy execution of the run is recorded.
+pub const UPGRADE_LOCK_HOLDER: i64 = 0;
+
+/// How often a held lock is tried again while waiting for it.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An exclusive lock on the deployments to a database, held until `release` is called.
+///
+/// PostgreSQL and MySQL locks belong to a database session, so the lock keeps a dedicated
+/// connection open and is released by the database if the process dies. SQLite has no such locks,
+/// so the lock is the `deploy_lock` row of the `deploy_log_config` table, which stays if the process
+/// dies and must then be released with `force_unlock`. In every database, the row records the
+/// deploy execution that holds the lock.
+pub struct DeployLock {
+    pool: AnyPool,
+    metadata: MetadataContext,
+    deploy_execution_id: i64,
+}
+
+impl DeployLock {
+    /// Releases the lock.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result`:
+    /// * `Ok(())` if the lock is released.
+    /// * `Err(Box<dyn Error>)` if the lock cannot be released, in which case it is released when
+    ///   the connection is closed, except in SQLite.
+    pub async fn release(self) -> Result<(), Box<dyn Error>> {
+        query(&format_query_with_schema(
+            "DELETE FROM {schema_prefix}deploy_log_config WHERE \"key\" = $1 AND value = $2;",
+            &self.metadata,
+        ))
+        .bind(LOCK_KEY)
+        .bind(self.deploy_execution_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        match self.metadata.kind {
+            DatabaseKind::PostgreSql => {
+                query("SELECT pg_advisory_unlock($1);")
+                    .bind(lock_id(&self.metadata))
+                    .execute(&self.pool)
+                    .await?;
+            }
+            DatabaseKind::MySql => {
+                query("SELECT RELEASE_LOCK(?);")
+                    .bind(lock_name(&self.metadata))
+                    .execute(&self.pool)
+                    .await?;
+            }
+            DatabaseKind::Sqlite => {}
+        }
+
+        self.pool.close().await;
+        Ok(())
+    }
+}
+
+/// Takes the exclusive deployment lock of the target database, waiting for it if it is held.
+///
+/// The lock is a PostgreSQL advisory lock, a MySQL `GET_LOCK` named lock or, in SQLite, the
+/// `deploy_lock` row of the `deploy_log_config` table. Locks are taken per metadata schema, so
+/// environments sharing a server with their own metadata schema do not wait for each other.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+/// * `deploy_execution_id` - The id of the deploy execution taking the lock.
+/// * `timeout` - How long to wait for a held lock.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(DeployLock)` with the lock, to release once the deployment is done.
+/// * `Err(Box<dyn Error>)` if the lock cannot be taken.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// * There is an issue connecting to the database.
+/// * The lock is still held after the timeout, the error names the deploy execution holding it.
+pub async fn acquire_deploy_lock(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    deploy_execution_id: i64,
+    timeout: Duration,
+) -> Result<DeployLock, Box<dyn Error>> {
+    // A single connection, so that the session locks are taken and released by the same session
+    let pool = AnyPoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect(connection_string)
+        .await?;
+
+    let deadline = Instant::now() + timeout;
+    while !try_lock(&pool, metadata, deploy_execution_id).await? {
+        let now = Instant::now();
+        if now >= deadline {
+            pool.close().await;
+            return Err(lock_held_error(connection_string, metadata, timeout)
+                .await
+                .into());
+        }
+        log::info!("Waiting for the deployment lock");
+        tokio::time::sleep(LOCK_POLL_INTERVAL.min(deadline - now)).await;
+    }
+
+    // Record the holder of a session lock, replacing the row left by a session that died
+    if metadata.kind != DatabaseKind::Sqlite {
+        query(&format_query_with_schema(
+            "DELETE FROM {schema_prefix}deploy_log_config WHERE \"key\" = $1;",
+            metadata,
+        ))
+        .bind(LOCK_KEY)
+        .execute(&pool)
+        .await?;
+        insert_lock_row(&pool, metadata, deploy_execution_id).await?;
+    }
+    log::info!(
+        "Deployment lock taken by deploy execution {}",
+        deploy_execution_id
+    );
+
+    Ok(DeployLock {
+        pool,
+        metadata: metadata.clone(),
+        deploy_execution_id,
+    })
+}
+
+/// Releases the deployment lock of the target database, whoever holds it.
+///
+/// The session holding a PostgreSQL or MySQL lock is terminated, the `deploy_lock` row is deleted,
+/// and the deploy execution holding the lock is marked as `FAILED` if it is still `RUNNING`. This
+/// is meant for stale locks, such as a SQLite lock left by a process that died.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(Some(i64))` with the id of the deploy execution that held the lock.
+/// * `Ok(None)` if the lock was not held by a deploy execution.
+/// * `Err(Box<dyn Error>)` if the lock cannot be released.
+pub async fn force_unlock(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<Option<i64>, Box<dyn Error>> {
+    let holder = lock_holder(connection_string, metadata).await?;
+
+    let pool = AnyPool::connect(connection_string).await?;
+    match metadata.kind {
+        DatabaseKind::PostgreSql => {
+            // Advisory locks on a bigint are listed with its high and low halves
+            let lock_id = lock_id(metadata);
+            query(
+                "SELECT pg_terminate_backend(pid) FROM pg_locks
+                    WHERE locktype = 'advisory' AND classid::bigint = $1 AND objid::bigint = $2
+                      AND objsubid = 1;",
+            )
+            .bind((lock_id >> 32) & 0xFFFF_FFFF)
+            .bind(lock_id & 0xFFFF_FFFF)
+            .execute(&pool)
+            .await?;
+        }
+        DatabaseKind::MySql => {
+            let session: Option<i64> = query_scalar("SELECT IS_USED_LOCK(?);")
+                .bind(lock_name(metadata))
+                .fetch_one(&pool)
+                .await?;
+            if let Some(session) = session {
+                query(&format!("KILL {};", session)).execute(&pool).await?;
+            }
+        }
+        DatabaseKind::Sqlite => {}
+    }
+    query(&format_query_with_schema(
+        "DELETE FROM {schema_prefix}deploy_log_config WHERE \"key\" = $1;",
+        metadata,
+    ))
+    .bind(LOCK_KEY)
+    .execute(&pool)
+    .await?;
+    pool.close().await;
+
+    if let Some(id) = holder {
+        let running = read_deploy_execution(connection_string, metadata, id)
+            .await?
+            .is_some_and(|execution| {
+                execution.status == DeployExecutionStatus::Running.to_string()
+            });
+        if running {
+            finish_deploy_execution(
+                connection_string,
+                metadata,
+                id,
+                DeployExecutionStatus::Failed,
+                Some("Deployment lock released with unlock --force"),
+            )
+            .await?;
+        }
+    }
+    Ok(holder)
+}
+
+/// Returns the id of the deploy execution recorded as the holder of the deployment lock, if any.
+///
+/// # Arguments
+///
+/// * `connection_string` - A string slice that holds the connection string to the target database.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
+pub async fn lock_holder(
+    connection_string: &str,
+    metadata: &MetadataContext,
+) -> Result<Option<i64>, Box<dyn Error>> {
+    Ok(read_config_value(connection_string, metadata, LOCK_KEY)
+        .await?
+        .and_then(|value| value.parse().ok()))
+}
+
+/// Tries to take the lock once, returning `false` if it is held.
+async fn try_lock(
+    pool: &AnyPool,
+    metadata: &MetadataContext,
+    deploy_execution_id: i64,
+) -> Result<bool, Box<dyn Error>> {
+    match metadata.kind {
+        DatabaseKind::PostgreSql => {
+            let locked: i64 =
+                query_scalar("SELECT CASE WHEN pg_try_advisory_lock($1) THEN 1 ELSE 0 END;")
+                    .bind(lock_id(metadata))
+                    .fetch_one(pool)
+                    .await?;
+            Ok(locked == 1)
+        }
+        DatabaseKind::MySql => {
+            let locked: Option<i64> = query_scalar("SELECT GET_LOCK(?, 0);")
+                .bind(lock_name(metadata))
+                .fetch_one(pool)
+                .await?;
+            Ok(locked == Some(1))
+        }
+        DatabaseKind::Sqlite => match insert_lock_row(pool, metadata, deploy_execution_id).await {
+            Ok(()) => Ok(true),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+/// Inserts the `deploy_lock` row, which fails if another deploy execution holds it.
+async fn insert_lock_row(
+    pool: &AnyPool,
+    metadata: &MetadataContext,
+    deploy_execution_id: i64,
+) -> Result<(), sqlx::Error> {
+    query(&format_query_with_schema(
+        "INSERT INTO {schema_prefix}deploy_log_config (\"key\", value) VALUES ($1, $2);",
+        metadata,
+    ))
+    .bind(LOCK_KEY)
+    .bind(deploy_execution_id.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Describes the holder of a lock that could not be taken.
+async fn lock_held_error(
+    connection_string: &str,
+    metadata: &MetadataContext,
+    timeout: Duration,
+) -> String {
+    let holder = match lock_holder(connection_string, metadata).await {
+        Ok(Some(id)) => read_deploy_execution(connection_string, metadata, id)
+            .await
+            .ok()
+            .flatten(),
+        _ => None,
+    };
+    let holder = match holder {
+        Some(execution) => format!(
+            "deploy execution #{} (requested by {}, executed by {}, started {}, status {})",
+            execution.id,
+            execution.requester,
+            execution.executor,
+            execution.time_started.as_deref().unwrap_or("-"),
+            execution.status
+        ),
+        None => "another session".to_string(),
+    };
+    format!(
+        "The deployment lock is held by {} after waiting {}s, release it with 'oxigration unlock --force' if it is stale",
+        holder,
+        timeout.as_secs()
+    )
+}
+
+/// Returns the key of the PostgreSQL advisory lock of the metadata schema.
+fn lock_id(metadata: &MetadataContext) -> i64 {
+    let digest = Sha256::digest(lock_name(metadata).as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    i64::from_be_bytes(bytes)
+}
+
+/// Returns the name of the MySQL lock of the metadata schema.
+fn lock_name(metadata: &MetadataContext) -> String {
+    format!("oxigration.{}", metadata.schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_log::start_deploy_execution;
+    use crate::deploy_log::tests::initialized_sqlite_database;
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+        let first =
+            start_deploy_execution(&connection_string, &metadata, "alice", "ci@a", "main").await?;
+        let second =
+            start_deploy_execution(&connection_string, &metadata, "bob", "ci@b", "main").await?;
+
+        let lock =
+            acquire_deploy_lock(&connection_string, &metadata, first, Duration::ZERO).await?;
+        assert_eq!(
+            lock_holder(&connection_string, &metadata).await?,
+            Some(first)
+        );
+
+        let error = acquire_deploy_lock(
+            &connection_string,
+            &metadata,
+            second,
+            Duration::from_millis(600),
+        )
+        .await
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("held by deploy execution #1 (requested by alice"));
+        assert!(error.contains("unlock --force"));
+
+        lock.release().await?;
+        assert_eq!(lock_holder(&connection_string, &metadata).await?, None);
+        acquire_deploy_lock(&connection_string, &metadata, second, Duration::ZERO)
+            .await?
+            .release()
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_force_unlock_releases_stale_lock() -> Result<(), Box<dyn Error>> {
+        let (_dir, connection_string, metadata) = initialized_sqlite_database().await?;
+        let stale =
+            start_deploy_execution(&connection_string, &metadata, "alice", "ci@a", "main").await?;
+        // A lock left by a process that died without releasing it
+        drop(acquire_deploy_lock(&connection_string, &metadata, stale, Duration::ZERO).await?);
+
+        assert_eq!(
+            force_unlock(&connection_string, &metadata).await?,
+            Some(stale)
+        );
+        let execution = read_deploy_execution(&connection_string, &metadata, stale)
+            .await?
+            .unwrap();
+        assert_eq!(execution.status, "FAILED");
+        assert_eq!(force_unlock(&connection_string, &metadata).await?, None);
+
+        let next =
+            start_deploy_execution(&connection_string, &metadata, "bob", "ci@b", "main").await?;
+        acquire_deploy_lock(&connection_string, &metadata, next, Duration::ZERO)
+            .await?
+            .release()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
 use clap::{Arg, Command};
-use env_logger;
-use oxigration::{generate, init, migrate};
-use tokio;
+use oxigration::{
+    generate, graph, init, migrate, plan, rollback, status, unlock, GraphFilter, GraphFormat,
+};
+use std::time::Duration;
 
 fn build_cli() -> Command {
     Command::new("oxigration")
@@ -12,11 +13,25 @@ fn build_cli() -> Command {
             Command::new("init")
                 .about("Initialize the oxigration metadata to keep track of schema migrations")
                 .arg(
-                    Arg::new("connection")
-                        .short('c')
-                        .long("connection")
-                        .default_value("postgresql://postgres@0.0.0.0/postgres")
-                        .help("Database connection string"),
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
+                )
+                .arg(
+                    Arg::new("lock-timeout")
+                        .long("lock-timeout")
+                        .default_value("60")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Seconds to wait for the deployment lock to upgrade the deploy log"),
                 ),
         )
         .subcommand(
@@ -30,11 +45,11 @@ fn build_cli() -> Command {
                         .help("Directory to store generated schemas"),
                 )
                 .arg(
-                    Arg::new("connection")
-                        .short('c')
-                        .long("connection")
-                        .default_value("postgresql://postgres@0.0.0.0/postgres")
-                        .help("Database connection string"),
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
                 ),
         )
         .subcommand(
@@ -48,11 +63,168 @@ fn build_cli() -> Command {
                         .help("Directory containing schema files"),
                 )
                 .arg(
-                    Arg::new("connection")
-                        .short('c')
-                        .long("connection")
-                        .default_value("postgresql://postgres@0.0.0.0/postgres")
-                        .help("Database connection string"),
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
+                )
+                .arg(
+                    Arg::new("lock-timeout")
+                        .long("lock-timeout")
+                        .default_value("60")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Seconds to wait for the deployment lock held by another migration"),
+                ),
+        )
+        .subcommand(
+            Command::new("plan")
+                .about("Print the ordered changes and SQL that migrate would apply, without executing them")
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("File to write the SQL script of the plan to"),
+                ),
+        )
+        .subcommand(
+            Command::new("graph")
+                .about("Export the dependency graph of the source code as Graphviz DOT, Mermaid or JSON")
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .help("Environment whose dialect, variables and schemas are used to read the schema files"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .default_value("dot")
+                        .value_parser(["dot", "mermaid", "json"])
+                        .help("Format of the graph"),
+                )
+                .arg(
+                    Arg::new("schema")
+                        .long("schema")
+                        .help("Only keep the changes of this schema"),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .help("Only keep the changes of this object type (table, view, ...)"),
+                )
+                .arg(
+                    Arg::new("dependents-of")
+                        .long("dependents-of")
+                        .help("Only keep this object or change and the changes that depend on it, directly or not"),
+                )
+                .arg(
+                    Arg::new("dependencies-of")
+                        .long("dependencies-of")
+                        .help("Only keep this object or change and the changes it depends on, directly or not"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("File to write the graph to"),
+                ),
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Undo the changes applied after a deploy execution")
+                .arg(
+                    Arg::new("to")
+                        .short('t')
+                        .long("to")
+                        .required(true)
+                        .value_parser(clap::value_parser!(i64))
+                        .help("Id of the last deploy execution to keep"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
+                )
+                .arg(
+                    Arg::new("lock-timeout")
+                        .long("lock-timeout")
+                        .default_value("60")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Seconds to wait for the deployment lock held by a migration"),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Summarize the changes applied, pending, modified or orphaned in the database")
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
+                ),
+        )
+        .subcommand(
+            Command::new("unlock")
+                .about("Release a stale deployment lock left by a migration that did not finish")
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Release the lock even though a deploy execution holds it"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .short('d')
+                        .long("dir")
+                        .default_value("schemas/")
+                        .help("Directory containing schema files"),
+                )
+                .arg(
+                    Arg::new("env")
+                        .short('e')
+                        .long("env")
+                        .default_value("DEV")
+                        .help("Environment declared in the oxigration.toml file of the schema directory"),
                 ),
         )
 }
@@ -96,11 +268,11 @@ async fn main() {
 
     match matches.subcommand() {
         Some(("init", sub_matches)) => {
-            let connection = sub_matches
-                .get_one::<String>("connection")
-                .unwrap()
-                .as_str();
-            if let Err(e) = init(connection).await {
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            let lock_timeout =
+                Duration::from_secs(*sub_matches.get_one::<u64>("lock-timeout").unwrap());
+            if let Err(e) = init(base_dir, environment, lock_timeout).await {
                 eprintln!("Error during initialization: {}", e);
             } else {
                 println!("Initialization completed successfully");
@@ -108,11 +280,8 @@ async fn main() {
         }
         Some(("generate", sub_matches)) => {
             let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
-            let connection = sub_matches
-                .get_one::<String>("connection")
-                .unwrap()
-                .as_str();
-            if let Err(e) = generate(base_dir, connection).await {
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            if let Err(e) = generate(base_dir, environment).await {
                 eprintln!("Error during generation: {}", e);
             } else {
                 println!("Generation completed successfully");
@@ -120,16 +289,74 @@ async fn main() {
         }
         Some(("migrate", sub_matches)) => {
             let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
-            let connection = sub_matches
-                .get_one::<String>("connection")
-                .unwrap()
-                .as_str();
-            if let Err(e) = migrate(base_dir, connection).await {
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            let lock_timeout =
+                Duration::from_secs(*sub_matches.get_one::<u64>("lock-timeout").unwrap());
+            if let Err(e) = migrate(base_dir, environment, lock_timeout).await {
                 eprintln!("Error during migration: {}", e);
             } else {
                 println!("Migration completed successfully");
             }
         }
+        Some(("plan", sub_matches)) => {
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            let output = sub_matches.get_one::<String>("output").map(|s| s.as_str());
+            match plan(base_dir, environment, output).await {
+                Ok(script) => print!("{}", script),
+                Err(e) => eprintln!("Error during planning: {}", e),
+            }
+        }
+        Some(("graph", sub_matches)) => {
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").map(|s| s.as_str());
+            let format: GraphFormat = sub_matches
+                .get_one::<String>("format")
+                .unwrap()
+                .parse()
+                .unwrap();
+            let filter = GraphFilter {
+                schema: sub_matches.get_one::<String>("schema").cloned(),
+                object_type: sub_matches.get_one::<String>("type").cloned(),
+                dependents_of: sub_matches.get_one::<String>("dependents-of").cloned(),
+                dependencies_of: sub_matches.get_one::<String>("dependencies-of").cloned(),
+            };
+            let output = sub_matches.get_one::<String>("output").map(|s| s.as_str());
+            match graph(base_dir, environment, format, &filter, output) {
+                Ok(graph) => print!("{}", graph),
+                Err(e) => eprintln!("Error exporting the graph: {}", e),
+            }
+        }
+        Some(("rollback", sub_matches)) => {
+            let deploy_execution_id = *sub_matches.get_one::<i64>("to").unwrap();
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            let lock_timeout =
+                Duration::from_secs(*sub_matches.get_one::<u64>("lock-timeout").unwrap());
+            if let Err(e) = rollback(base_dir, environment, deploy_execution_id, lock_timeout).await
+            {
+                eprintln!("Error during rollback: {}", e);
+            } else {
+                println!("Rollback completed successfully");
+            }
+        }
+        Some(("status", sub_matches)) => {
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            match status(base_dir, environment).await {
+                Ok(report) => print!("{}", report),
+                Err(e) => eprintln!("Error reading status: {}", e),
+            }
+        }
+        Some(("unlock", sub_matches)) => {
+            let force = sub_matches.get_flag("force");
+            let base_dir = sub_matches.get_one::<String>("dir").unwrap().as_str();
+            let environment = sub_matches.get_one::<String>("env").unwrap().as_str();
+            match unlock(base_dir, environment, force).await {
+                Ok(message) => println!("{}", message),
+                Err(e) => eprintln!("Error during unlock: {}", e),
+            }
+        }
         _ => unreachable!(),
     }
 }
@@ -142,27 +369,18 @@ mod tests {
     fn test_cli_init() {
         let cmd = build_cli();
 
-        let matches = cmd.try_get_matches_from(vec![
-            "oxigration",
-            "init",
-            "-c",
-            "postgresql://test@localhost/test",
-        ]);
+        let matches = cmd.try_get_matches_from(vec!["oxigration", "init", "--env", "TEST"]);
 
         assert!(matches.is_ok());
         let matches = matches.unwrap();
         assert_eq!(matches.subcommand_name(), Some("init"));
         if let Some(sub_matches) = matches.subcommand_matches("init") {
-            let _c = sub_matches.get_one::<String>("connection").unwrap();
-            assert_eq!(
-                sub_matches.get_one::<String>("connection").unwrap(),
-                "postgresql://test@localhost/test"
-            );
+            assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "TEST");
         }
     }
 
     #[test]
-    fn test_cli_init_default_connection() {
+    fn test_cli_init_default_env() {
         let cmd = build_cli();
 
         let matches = cmd.try_get_matches_from(vec!["oxigration", "init"]);
@@ -171,10 +389,8 @@ mod tests {
         let matches = matches.unwrap();
         assert_eq!(matches.subcommand_name(), Some("init"));
         if let Some(sub_matches) = matches.subcommand_matches("init") {
-            assert_eq!(
-                sub_matches.get_one::<String>("connection").unwrap(),
-                "postgresql://postgres@0.0.0.0/postgres"
-            );
+            assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "DEV");
+            assert_eq!(sub_matches.get_one::<u64>("lock-timeout"), Some(&60));
         }
     }
 
@@ -187,8 +403,8 @@ mod tests {
             "migrate",
             "-d",
             "tests/schemas/baseline/",
-            "-c",
-            "postgresql://test@localhost/test",
+            "--env",
+            "TEST",
         ]);
 
         assert!(matches.is_ok());
@@ -199,10 +415,7 @@ mod tests {
                 sub_matches.get_one::<String>("dir").unwrap(),
                 "tests/schemas/baseline/"
             );
-            assert_eq!(
-                sub_matches.get_one::<String>("connection").unwrap(),
-                "postgresql://test@localhost/test"
-            );
+            assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "TEST");
         }
     }
 
@@ -215,8 +428,8 @@ mod tests {
             "generate",
             "-d",
             "tests/schemas/generated/",
-            "-c",
-            "postgresql://test@localhost/test",
+            "--env",
+            "TEST",
         ]);
 
         assert!(matches.is_ok());
@@ -227,10 +440,126 @@ mod tests {
                 sub_matches.get_one::<String>("dir").unwrap(),
                 "tests/schemas/generated/"
             );
+            assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "TEST");
+        }
+    }
+
+    #[test]
+    fn test_cli_plan() {
+        let cmd = build_cli();
+
+        let matches = cmd.try_get_matches_from(vec![
+            "oxigration",
+            "plan",
+            "-d",
+            "tests/schemas/baseline/",
+            "-o",
+            "plan.sql",
+        ]);
+
+        assert!(matches.is_ok());
+        let matches = matches.unwrap();
+        assert_eq!(matches.subcommand_name(), Some("plan"));
+        if let Some(sub_matches) = matches.subcommand_matches("plan") {
             assert_eq!(
-                sub_matches.get_one::<String>("connection").unwrap(),
-                "postgresql://test@localhost/test"
+                sub_matches.get_one::<String>("dir").unwrap(),
+                "tests/schemas/baseline/"
             );
+            assert_eq!(sub_matches.get_one::<String>("output").unwrap(), "plan.sql");
         }
     }
+
+    #[test]
+    fn test_cli_status() {
+        let cmd = build_cli();
+
+        let matches = cmd.try_get_matches_from(vec!["oxigration", "status", "-e", "PROD"]);
+
+        assert!(matches.is_ok());
+        let matches = matches.unwrap();
+        assert_eq!(matches.subcommand_name(), Some("status"));
+        if let Some(sub_matches) = matches.subcommand_matches("status") {
+            assert_eq!(sub_matches.get_one::<String>("dir").unwrap(), "schemas/");
+            assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "PROD");
+        }
+    }
+
+    #[test]
+    fn test_cli_rollback() {
+        let cmd = build_cli();
+
+        let matches = cmd.try_get_matches_from(vec!["oxigration", "rollback", "--to", "3"]);
+
+        assert!(matches.is_ok());
+        let matches = matches.unwrap();
+        assert_eq!(matches.subcommand_name(), Some("rollback"));
+        if let Some(sub_matches) = matches.subcommand_matches("rollback") {
+            assert_eq!(*sub_matches.get_one::<i64>("to").unwrap(), 3);
+            assert_eq!(*sub_matches.get_one::<u64>("lock-timeout").unwrap(), 60);
+        }
+
+        let cmd = build_cli();
+        assert!(cmd
+            .try_get_matches_from(vec!["oxigration", "rollback"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_cli_migrate_lock_timeout() {
+        let matches = build_cli()
+            .try_get_matches_from(vec!["oxigration", "migrate", "--lock-timeout", "5"])
+            .unwrap();
+        let sub_matches = matches.subcommand_matches("migrate").unwrap();
+        assert_eq!(*sub_matches.get_one::<u64>("lock-timeout").unwrap(), 5);
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["oxigration", "migrate"])
+            .unwrap();
+        let sub_matches = matches.subcommand_matches("migrate").unwrap();
+        assert_eq!(*sub_matches.get_one::<u64>("lock-timeout").unwrap(), 60);
+    }
+
+    #[test]
+    fn test_cli_unlock() {
+        let matches = build_cli()
+            .try_get_matches_from(vec!["oxigration", "unlock", "--force", "-e", "TEST"])
+            .unwrap();
+        assert_eq!(matches.subcommand_name(), Some("unlock"));
+        let sub_matches = matches.subcommand_matches("unlock").unwrap();
+        assert!(sub_matches.get_flag("force"));
+        assert_eq!(sub_matches.get_one::<String>("env").unwrap(), "TEST");
+    }
+
+    #[test]
+    fn test_cli_graph() {
+        let matches = build_cli()
+            .try_get_matches_from(vec![
+                "oxigration",
+                "graph",
+                "-d",
+                "tests/schemas/baseline/",
+                "--format",
+                "mermaid",
+                "--dependents-of",
+                "baseline.table_a",
+            ])
+            .unwrap();
+        assert_eq!(matches.subcommand_name(), Some("graph"));
+        let sub_matches = matches.subcommand_matches("graph").unwrap();
+        assert_eq!(sub_matches.get_one::<String>("format").unwrap(), "mermaid");
+        assert_eq!(
+            sub_matches.get_one::<String>("dependents-of").unwrap(),
+            "baseline.table_a"
+        );
+
+        let matches = build_cli()
+            .try_get_matches_from(vec!["oxigration", "graph"])
+            .unwrap();
+        let sub_matches = matches.subcommand_matches("graph").unwrap();
+        assert_eq!(sub_matches.get_one::<String>("format").unwrap(), "dot");
+
+        assert!(build_cli()
+            .try_get_matches_from(vec!["oxigration", "graph", "--format", "svg"])
+            .is_err());
+    }
 }
@@ -0,0 +1,206 @@
+use sqlparser::ast::{
+    AlterTableOperation, CreateIndex, FunctionDesc, ObjectName, Statement, TableConstraint,
+};
+
+/// Derives the statements that undo a change from its parsed statements.
+///
+/// Only the statements whose inverse does not lose anything are inverted:
+///
+/// * `CREATE TABLE` is undone by `DROP TABLE`.
+/// * `ALTER TABLE ... ADD COLUMN` is undone by `ALTER TABLE ... DROP COLUMN`.
+/// * `ALTER TABLE ... ADD CONSTRAINT` is undone by `ALTER TABLE ... DROP CONSTRAINT`.
+/// * `CREATE SEQUENCE`, `CREATE [MATERIALIZED] VIEW` and `CREATE INDEX` are undone by the
+///   matching `DROP`.
+/// * `CREATE FUNCTION` is undone by `DROP FUNCTION` with its argument types, so that only the
+///   same overload is dropped.
+///
+/// The statements of a change are inverted in reverse order, so that the rollback of a change
+/// that creates a table and then adds a constraint drops the constraint before the table.
+///
+/// # Arguments
+///
+/// * `statements` - The parsed statements of the change, in execution order.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with the rollback statements, one per line.
+/// * `Err(String)` with the reason why the change cannot be inverted, such as a lossy operation
+///   (dropping a column, deleting rows, ...) whose data cannot be restored.
+pub fn derive_rollback(statements: &[Statement]) -> Result<String, String> {
+    let mut rollback = Vec::new();
+    for statement in statements.iter().rev() {
+        rollback.extend(inverse_statements(statement)?);
+    }
+    if rollback.is_empty() {
+        return Err("the change has no statements".to_string());
+    }
+    Ok(rollback.join("\n"))
+}
+
+/// Returns the statements that undo a single statement.
+fn inverse_statements(statement: &Statement) -> Result<Vec<String>, String> {
+    match statement {
+        Statement::CreateTable(create_table) => {
+            Ok(vec![format!("DROP TABLE {};", create_table.name)])
+        }
+        Statement::CreateView {
+            name, materialized, ..
+        } => Ok(vec![format!(
+            "DROP {}VIEW {};",
+            if *materialized { "MATERIALIZED " } else { "" },
+            name
+        )]),
+        Statement::CreateSequence { name, .. } => Ok(vec![format!("DROP SEQUENCE {};", name)]),
+        Statement::CreateIndex(CreateIndex {
+            name: Some(name), ..
+        }) => Ok(vec![format!("DROP INDEX {};", name)]),
+        Statement::CreateFunction { name, args, .. } => Ok(vec![format!(
+            "DROP FUNCTION {};",
+            FunctionDesc {
+                name: name.clone(),
+                args: args.clone(),
+            }
+        )]),
+        Statement::AlterTable {
+            name, operations, ..
+        } => operations
+            .iter()
+            .rev()
+            .map(|operation| inverse_alter_table_operation(name, operation))
+            .collect(),
+        Statement::Drop { .. }
+        | Statement::Truncate { .. }
+        | Statement::Delete(_)
+        | Statement::Update { .. } => Err(format!(
+            "'{}' loses data and cannot be rolled back automatically",
+            statement_kind(statement)
+        )),
+        _ => Err(format!(
+            "'{}' has no automatic rollback",
+            statement_kind(statement)
+        )),
+    }
+}
+
+/// Returns the statement that undoes an operation of an `ALTER TABLE` statement.
+fn inverse_alter_table_operation(
+    table_name: &ObjectName,
+    operation: &AlterTableOperation,
+) -> Result<String, String> {
+    match operation {
+        AlterTableOperation::AddColumn { column_def, .. } => Ok(format!(
+            "ALTER TABLE {} DROP COLUMN {};",
+            table_name, column_def.name
+        )),
+        AlterTableOperation::AddConstraint(constraint) => match constraint_name(constraint) {
+            Some(name) => Ok(format!(
+                "ALTER TABLE {} DROP CONSTRAINT {};",
+                table_name, name
+            )),
+            None => Err(format!(
+                "'{}' adds a constraint without a name, which cannot be dropped",
+                constraint
+            )),
+        },
+        AlterTableOperation::DropColumn { .. }
+        | AlterTableOperation::DropConstraint { .. }
+        | AlterTableOperation::DropPrimaryKey
+        | AlterTableOperation::AlterColumn { .. }
+        | AlterTableOperation::ChangeColumn { .. }
+        | AlterTableOperation::ModifyColumn { .. } => Err(format!(
+            "'ALTER TABLE {} {}' loses data or definitions and cannot be rolled back automatically",
+            table_name, operation
+        )),
+        _ => Err(format!(
+            "'ALTER TABLE {} {}' has no automatic rollback",
+            table_name, operation
+        )),
+    }
+}
+
+/// Returns the name of a table constraint added by `ADD CONSTRAINT`.
+fn constraint_name(constraint: &TableConstraint) -> Option<&sqlparser::ast::Ident> {
+    match constraint {
+        TableConstraint::Unique { name, .. }
+        | TableConstraint::PrimaryKey { name, .. }
+        | TableConstraint::ForeignKey { name, .. }
+        | TableConstraint::Check { name, .. } => name.as_ref(),
+        _ => None,
+    }
+}
+
+/// Returns the leading keywords of a statement (e.g. `DROP TABLE`), used in error messages.
+fn statement_kind(statement: &Statement) -> String {
+    statement
+        .to_string()
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::dialect::PostgreSqlDialect;
+    use sqlparser::parser::Parser;
+
+    fn rollback_of(sql: &str) -> Result<String, String> {
+        derive_rollback(&Parser::parse_sql(&PostgreSqlDialect {}, sql).unwrap())
+    }
+
+    #[test]
+    fn test_derive_rollback_of_simple_ddl() {
+        assert_eq!(
+            rollback_of("CREATE TABLE t1 (id INT);"),
+            Ok("DROP TABLE t1;".to_string())
+        );
+        assert_eq!(
+            rollback_of("ALTER TABLE t1 ADD COLUMN name TEXT;"),
+            Ok("ALTER TABLE t1 DROP COLUMN name;".to_string())
+        );
+        assert_eq!(
+            rollback_of("ALTER TABLE t1 ADD CONSTRAINT t1_pkey PRIMARY KEY (id);"),
+            Ok("ALTER TABLE t1 DROP CONSTRAINT t1_pkey;".to_string())
+        );
+        assert_eq!(
+            rollback_of("CREATE SEQUENCE s1 INCREMENT BY 1;"),
+            Ok("DROP SEQUENCE s1;".to_string())
+        );
+        assert_eq!(
+            rollback_of("CREATE MATERIALIZED VIEW v1 AS SELECT 1;"),
+            Ok("DROP MATERIALIZED VIEW v1;".to_string())
+        );
+        assert_eq!(
+            rollback_of("CREATE INDEX t1_name ON t1 (name);"),
+            Ok("DROP INDEX t1_name;".to_string())
+        );
+        assert_eq!(
+            rollback_of(
+                "CREATE FUNCTION f1(var1 integer) RETURNS integer LANGUAGE sql AS 'SELECT 1';"
+            ),
+            Ok("DROP FUNCTION f1(var1 INTEGER);".to_string())
+        );
+    }
+
+    #[test]
+    fn test_derive_rollback_in_reverse_order() {
+        assert_eq!(
+            rollback_of(
+                "CREATE TABLE t1 (id INT); ALTER TABLE t1 ADD CONSTRAINT t1_pkey PRIMARY KEY (id);"
+            ),
+            Ok("ALTER TABLE t1 DROP CONSTRAINT t1_pkey;\nDROP TABLE t1;".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lossy_operations_are_not_inverted() {
+        let error = rollback_of("ALTER TABLE t1 DROP COLUMN name;").unwrap_err();
+        assert!(error.contains("cannot be rolled back automatically"));
+        assert!(rollback_of("DROP TABLE t1;").is_err());
+        assert!(rollback_of("DELETE FROM t1;").is_err());
+        assert!(rollback_of("CREATE TABLE t2 (id INT); ALTER TABLE t1 DROP COLUMN name;").is_err());
+        assert!(rollback_of("ALTER TABLE t1 ADD PRIMARY KEY (id);").is_err());
+    }
+}
@@ -1,8 +1,14 @@
-use crate::utils::topsort::topo_sort;
+use crate::dependency_graph::{dependency_edges, describe_cycle};
+use crate::environment::{interpolate, Environment};
+use crate::rollback::derive_rollback;
+use crate::utils::topsort::{topo_sort, TopologicalSortError};
+use crate::utils::{content_hash, SqlDialect};
 use core::ops::ControlFlow;
 use indexmap::IndexMap;
-use sqlparser::ast::{ObjectName, Statement, Visitor};
-use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::ast::{
+    AlterTableOperation, ColumnDef, ColumnOption, Expr, FunctionArg, FunctionArgExpr,
+    FunctionArguments, ObjectName, Query, Statement, TableConstraint, Value, Visit, Visitor,
+};
 use sqlparser::parser::Parser;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
@@ -11,6 +17,9 @@ use std::io::Read;
 use std::path::Path;
 use walkdir::WalkDir;
 
+/// The line that starts the rollback section of a change, which runs until the end of the change.
+const ROLLBACK_DELIMITER: &str = "//// ROLLBACK";
+
 /// Represents a database object with associated metadata.
 ///
 /// This struct encapsulates a named database object along with its value,
@@ -23,10 +32,37 @@ pub struct DatabaseObject {
     pub value: String,
     /// A set of dependencies for this database object.
     pub dependencies: HashSet<String>,
+    /// The objects referenced by the statements of the change, as `name` or `schema.name`.
+    pub inferred_dependencies: HashSet<Reference>,
+    /// The keys of the changes this change depends on, resolved from `dependencies` and
+    /// `inferred_dependencies` when the source code is ordered.
+    pub dependency_keys: Vec<String>,
     /// Additional properties associated with the database object.
     pub _properties: HashMap<String, String>,
     /// The parsed SQL content of the database object.
     pub parsed_content: Option<Statement>,
+    /// The hash of the change content, compared with `content_hash` in the deploy log.
+    pub content_hash: String,
+    /// The schema directory the change belongs to.
+    pub schema_name: String,
+    /// The object type directory the change belongs to (e.g. table, view).
+    pub object_type: String,
+    /// The name of the database object the change applies to.
+    pub object_name: String,
+    /// When the change was applied, for changes read from the deploy log.
+    pub applied_at: Option<String>,
+    /// The statements that undo the change.
+    pub rollback_content: Option<String>,
+    /// The deploy execution that applied the change, for changes read from the deploy log.
+    pub deploy_execution_id: Option<i64>,
+    /// The physical schema the change was applied to, for changes read from the deploy log.
+    pub physical_schema: Option<String>,
+    /// The file the change was read from, for changes read from the source code.
+    pub file_path: String,
+    /// The line of the file where the value of the change starts.
+    pub line: usize,
+    /// The line of the file where the `//// ROLLBACK` section of the change starts, if any.
+    pub rollback_line: usize,
 }
 impl DatabaseObject {
     /// Creates a new DatabaseObject with the given parameters.
@@ -44,12 +80,85 @@ impl DatabaseObject {
             }
         }
 
+        // The hash is computed on the raw change text, `relational_object_conformance` replaces it
+        // with the hash of the parsed statements for changes with the `hash=ast` attribute
+        let content_hash = content_hash(&value);
+
         DatabaseObject {
             change_name,
             value,
             dependencies,
+            inferred_dependencies: HashSet::new(),
+            dependency_keys: Vec::new(),
             _properties: properties,
             parsed_content,
+            content_hash,
+            schema_name: String::new(),
+            object_type: String::new(),
+            object_name: String::new(),
+            applied_at: None,
+            rollback_content: None,
+            deploy_execution_id: None,
+            physical_schema: None,
+            file_path: String::new(),
+            line: 0,
+            rollback_line: 0,
+        }
+    }
+
+    /// Returns the name of the change within its object, without the `schema.type.object.` prefix.
+    pub fn short_change_name(&self) -> &str {
+        let prefix = format!(
+            "{}.{}.{}.",
+            self.schema_name, self.object_type, self.object_name
+        );
+        self.change_name
+            .strip_prefix(&prefix)
+            .unwrap_or(&self.change_name)
+    }
+
+    /// Returns the `schema.object` name under which the change is recorded in the deploy log.
+    pub fn qualified_object_name(&self) -> String {
+        format!("{}.{}", self.schema_name, self.object_name)
+    }
+}
+
+/// How the statements of a change use a referenced object, which decides the object types the
+/// reference resolves to.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ReferenceKind {
+    /// A relation of a query, DML statement, index, `REFERENCES` clause or trigger, or the
+    /// sequence of a `nextval('...')` call.
+    Relation,
+    /// A called function, or the function of a trigger.
+    Function,
+}
+
+impl ReferenceKind {
+    /// Returns the object types the references of this kind resolve to.
+    pub fn object_types(self) -> &'static [&'static str] {
+        match self {
+            ReferenceKind::Relation => &["table", "view", "sequence"],
+            ReferenceKind::Function => &["function", "sp", "procedure"],
+        }
+    }
+}
+
+/// An object referenced by the statements of a change.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Reference {
+    /// The referenced object, as `name` or `schema.name`.
+    pub name: String,
+    /// How the statements use the object.
+    pub kind: ReferenceKind,
+}
+
+impl Reference {
+    /// Creates a reference to an object used as a given kind.
+    pub fn new(name: &str, kind: ReferenceKind) -> Self {
+        Reference {
+            name: name.to_string(),
+            kind,
         }
     }
 }
@@ -121,6 +230,137 @@ impl Visitor for SqlVisitor {
     }
 }
 
+/// Visitor collecting the objects referenced by the statements of a change.
+///
+/// The references are the relations of `FROM`/`JOIN` clauses, DML statements and indexes, the
+/// tables of `REFERENCES` clauses, the sequences of `nextval('...')` calls, the called functions
+/// and the table and function of triggers. The names of common table expressions are not
+/// references. A database qualifier is dropped, so every reference is `name` or `schema.name`.
+/// The called functions are function references and the other objects relation references.
+#[derive(Default)]
+struct ReferenceVisitor {
+    references: HashSet<Reference>,
+    cte_names: HashSet<String>,
+}
+impl ReferenceVisitor {
+    fn add_reference(&mut self, name: &ObjectName, kind: ReferenceKind) {
+        self.add_name_parts(
+            name.0.iter().map(|ident| ident.value.as_str()).collect(),
+            kind,
+        );
+    }
+
+    fn add_name_parts(&mut self, parts: Vec<&str>, kind: ReferenceKind) {
+        let qualified_name = parts[parts.len().saturating_sub(2)..].join(".");
+        if !qualified_name.is_empty() {
+            self.references
+                .insert(Reference::new(&qualified_name, kind));
+        }
+    }
+
+    fn add_column_references(&mut self, column: &ColumnDef) {
+        for option in &column.options {
+            if let ColumnOption::ForeignKey { foreign_table, .. } = &option.option {
+                self.add_reference(foreign_table, ReferenceKind::Relation);
+            }
+        }
+    }
+
+    fn add_constraint_references(&mut self, constraint: &TableConstraint) {
+        if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+            self.add_reference(foreign_table, ReferenceKind::Relation);
+        }
+    }
+
+    /// Returns the references, without the common table expressions of the statements.
+    fn into_references(self) -> HashSet<Reference> {
+        let cte_names = self.cte_names;
+        self.references
+            .into_iter()
+            .filter(|reference| {
+                reference.kind != ReferenceKind::Relation || !cte_names.contains(&reference.name)
+            })
+            .collect()
+    }
+}
+impl Visitor for ReferenceVisitor {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.cte_names.insert(cte.alias.name.value.clone());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        self.add_reference(relation, ReferenceKind::Relation);
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if let Expr::Function(function) = expr {
+            self.add_reference(&function.name, ReferenceKind::Function);
+            // The sequence of `nextval('seq')` is a string, optionally cast to `regclass`
+            if function.name.to_string().eq_ignore_ascii_case("nextval") {
+                if let FunctionArguments::List(list) = &function.args {
+                    if let Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))) =
+                        list.args.first()
+                    {
+                        let arg = match arg {
+                            Expr::Cast { expr, .. } => expr.as_ref(),
+                            arg => arg,
+                        };
+                        if let Expr::Value(Value::SingleQuotedString(sequence)) = arg {
+                            let parts = sequence.split('.').map(|p| p.trim_matches('"'));
+                            self.add_name_parts(parts.collect(), ReferenceKind::Relation);
+                        }
+                    }
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, stmt: &Statement) -> ControlFlow<Self::Break> {
+        match stmt {
+            Statement::CreateTable(stmt) => {
+                for column in &stmt.columns {
+                    self.add_column_references(column);
+                }
+                for constraint in &stmt.constraints {
+                    self.add_constraint_references(constraint);
+                }
+            }
+            Statement::AlterTable { operations, .. } => {
+                for operation in operations {
+                    match operation {
+                        AlterTableOperation::AddColumn { column_def, .. } => {
+                            self.add_column_references(column_def);
+                        }
+                        AlterTableOperation::AddConstraint(constraint) => {
+                            self.add_constraint_references(constraint);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Statement::CreateTrigger {
+                table_name,
+                exec_body,
+                ..
+            } => {
+                self.add_reference(table_name, ReferenceKind::Relation);
+                self.add_reference(&exec_body.func_desc.name, ReferenceKind::Function);
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
 /// Reads and processes a directory containing multiple subdirectories, each representing a type of
 /// database object.
 ///
@@ -165,6 +405,10 @@ impl Visitor for SqlVisitor {
 /// # Arguments
 ///
 /// * `base_dir` - A string slice that holds the base directory path.
+/// * `environment` - The environment whose variables resolve the `${token}` placeholders of the
+///   changes, and whose SQL dialect parses them, if any. Only the changes of its schemas whose
+///   `includeEnvs` and `excludeEnvs` attributes select it are read, before their placeholders are
+///   resolved. The changes are parsed as PostgreSQL without an environment.
 ///
 /// # Errors
 ///
@@ -172,17 +416,27 @@ impl Visitor for SqlVisitor {
 ///
 /// * If the file cannot be opened or read.
 /// * If the file contains invalid UTF-8 data.
+/// * If a change has a `${token}` placeholder that is not a variable of the environment.
+/// * If a change cannot be parsed with the SQL dialect of the environment.
 pub fn read_source_code(
     base_dir: &str,
+    environment: Option<&Environment>,
 ) -> Result<IndexMap<String, DatabaseObject>, Box<dyn Error>> {
+    let no_variables = HashMap::new();
+    let variables = environment.map_or(&no_variables, |environment| &environment.variables);
+    let dialect = environment.map_or(SqlDialect::PostgreSql, Environment::sql_dialect);
+
     let mut object_info: IndexMap<String, DatabaseObject> = IndexMap::new();
 
     log::debug!("Reading desired state from {}", base_dir);
     // Traverse the directory structure
-    for entry in WalkDir::new(base_dir).into_iter().filter_map(|e| e.ok()) {
+    for entry in WalkDir::new(base_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
         // Check if the entry is a file with a .sql extension
-        if entry.file_type().is_file() && entry.path().extension().map_or(false, |ext| ext == "sql")
-        {
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "sql") {
             let file_path = entry.path();
             let schema_name = file_path
                 .parent()
@@ -204,11 +458,24 @@ pub fn read_source_code(
 
             // Parse the SQL statements in the file
             let parsed_stmts = parse_change_stmts(&contents, "//// CHANGE", "GO", "name");
-            // Iterate over the parsed statements
+            // Iterate over the parsed statements deployed to the environment
             for (_, mut stmt) in parsed_stmts {
+                if let Some(environment) = environment {
+                    if !environment.allows_schema(schema_name)
+                        || !environment.includes_change(&stmt._properties)
+                    {
+                        continue;
+                    }
+                }
                 // Build a relational object from the parsed statement
-                match relational_object_conformance(file_path, schema_name, object_type, &mut stmt)
-                {
+                match relational_object_conformance(
+                    file_path,
+                    schema_name,
+                    object_type,
+                    &mut stmt,
+                    variables,
+                    dialect,
+                ) {
                     Ok(_) => {
                         object_info.insert(stmt.change_name.clone(), stmt);
                     }
@@ -246,6 +513,8 @@ pub fn read_source_code(
 /// * `object_type` - A string slice representing the type of the object (e.g., table, view).
 /// * `contents` - A string slice containing the contents of the SQL file.
 /// * `stmt` - A mutable reference to a `DatabaseObject` to be updated.
+/// * `variables` - The variables resolving the `${token}` placeholders of the change.
+/// * `dialect` - The SQL dialect the change is parsed with.
 ///
 /// # Returns
 ///
@@ -258,6 +527,8 @@ fn relational_object_conformance(
     schema_name: &str,
     object_type: &str,
     stmt: &mut DatabaseObject,
+    variables: &HashMap<String, String>,
+    dialect: SqlDialect,
 ) -> Result<(), Box<dyn Error>> {
     // Extract the file name from the file path
     let file_name = file_path
@@ -266,19 +537,73 @@ fn relational_object_conformance(
         .map(|s| s.to_string())
         .ok_or_else(|| format!("Failed to extract file stem from path: {:?}", file_path))?;
 
+    stmt.file_path = file_path.display().to_string();
+
+    // Tokens are resolved before parsing, while the hash stays the one of the unresolved text so
+    // that the change hashes identically in every environment
+    let unresolved_value = stmt.value.clone();
+    stmt.value = resolve_tokens(&stmt.value, &stmt.file_path, stmt.line, variables)?;
+    if let Some(rollback) = &stmt.rollback_content {
+        stmt.rollback_content = Some(resolve_tokens(
+            rollback,
+            &stmt.file_path,
+            stmt.rollback_line,
+            variables,
+        )?);
+    }
+
     // Parse the SQL content to extract the first SQL object
-    let dialect = PostgreSqlDialect {};
-    let parsed_content = match Parser::parse_sql(&dialect, &stmt.value) {
-        Ok(content) => content
-            .first()
-            .cloned() // Clone the first element to extend its lifetime
-            .ok_or("No objects found in parsed content")?,
-        Err(e) => return Err(Box::new(e)),
+    let parse = |sql: &str| {
+        Parser::parse_sql(dialect.parser_dialect().as_ref(), sql).map_err(|e| {
+            format!(
+                "Failed to parse change '{}' in {}:{} with the {} dialect: {}",
+                stmt.change_name, stmt.file_path, stmt.line, dialect, e
+            )
+        })
     };
+    let parsed_statements = parse(&stmt.value)?;
+    let parsed_content = parsed_statements
+        .first()
+        .cloned() // Clone the first element to extend its lifetime
+        .ok_or("No objects found in parsed content")?;
+
+    // Changes with the `hash=ast` attribute are hashed on their parsed statements, so that any
+    // change that does not modify the AST (keyword case, quoting, ...) keeps the same hash
+    if stmt._properties.get("hash").map(String::as_str) == Some("ast") {
+        // Tokens are replaced by their names, which keeps the AST independent of the environment
+        let named_tokens = interpolate(&unresolved_value, |token| Some(token.to_string()))
+            .unwrap_or(unresolved_value);
+        let rendered: Vec<String> = parse(&named_tokens)?
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        stmt.content_hash = content_hash(&rendered.join(";\n"));
+    }
+
+    // Changes without a hand-written rollback get one derived from their statements, unless
+    // undoing them would lose data
+    if stmt.rollback_content.is_none() {
+        match derive_rollback(&parsed_statements) {
+            Ok(rollback) => stmt.rollback_content = Some(rollback),
+            Err(reason) => log::warn!(
+                "No rollback for change '{}' in {}: {}",
+                stmt.change_name,
+                file_path.display(),
+                reason
+            ),
+        }
+    }
+
+    // Record the objects referenced by the statements, the change is executed after them
+    let mut references = ReferenceVisitor::default();
+    for statement in &parsed_statements {
+        let _ = statement.visit(&mut references);
+    }
+    stmt.inferred_dependencies = references.into_references();
 
     // Use a visitor to traverse the SQL statement and gather necessary information
     let mut visitor = SqlVisitor::new();
-    visitor.pre_visit_statement(&parsed_content); // Use pre_visit_statement method
+    let _ = visitor.pre_visit_statement(&parsed_content); // Use pre_visit_statement method
 
     // Check if the file name matches the object name
     if file_name != visitor.object_name {
@@ -307,10 +632,43 @@ fn relational_object_conformance(
     // Update the existing DatabaseObject
     stmt.change_name = key;
     stmt.parsed_content = Some(parsed_content);
+    stmt.schema_name = schema_name.to_string();
+    stmt.object_type = object_type.to_string();
+    stmt.object_name = file_name;
 
     Ok(())
 }
 
+/// Resolves the `${token}` placeholders of a change with the variables of the environment.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with every token replaced by its value.
+/// * `Err(Box<dyn Error>)` listing every unresolved token with its file and line.
+fn resolve_tokens(
+    text: &str,
+    file_path: &str,
+    first_line: usize,
+    variables: &HashMap<String, String>,
+) -> Result<String, Box<dyn Error>> {
+    interpolate(text, |token| variables.get(token).cloned()).map_err(|unresolved| {
+        unresolved
+            .iter()
+            .map(|(token, offset)| {
+                format!(
+                    "Unresolved token '${{{}}}' in {}:{}",
+                    token,
+                    file_path,
+                    first_line + text[..*offset].matches('\n').count()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into()
+    })
+}
+
 /// Parses a string containing multiple SQL statements delimited by specified start and end delimiters.
 ///
 /// This function processes the input string `content` to extract SQL statements that are enclosed
@@ -329,7 +687,11 @@ fn relational_object_conformance(
 ///
 /// This function returns an `IndexMap` where the keys are the unique identifiers for each SQL statement
 /// (derived from the attributes or generated as "rootN" if not specified), and the values are `DatabaseObject`
-/// instances containing the parsed SQL statement, its attributes, and dependencies.
+/// instances containing the parsed SQL statement, its attributes, dependencies and content hash.
+///
+/// Inside a change, the lines that follow a `//// ROLLBACK` line up to the end delimiter are the
+/// statements that undo the change. They are stored in `rollback_content` and excluded from the
+/// value of the change and its hash.
 fn parse_change_stmts(
     content: &str,
     start_delimiter: &str,
@@ -343,9 +705,16 @@ fn parse_change_stmts(
     let mut in_statement = false;
     let mut root_counter = 0;
     let mut change_name = String::new();
+    let mut rollback = String::new();
+    let mut in_rollback = false;
+    // The lines of the file where the value and the rollback of the current change start
+    let mut start_line = 0;
+    let mut rollback_start_line = 0;
 
-    for line in content.lines() {
-        if line.trim().starts_with(start_delimiter) {
+    for (index, line) in content.lines().enumerate() {
+        if in_statement && line.trim().starts_with(ROLLBACK_DELIMITER) {
+            in_rollback = true;
+        } else if line.trim().starts_with(start_delimiter) {
             in_statement = true;
             properties = line
                 .trim_start_matches(start_delimiter)
@@ -362,40 +731,51 @@ fn parse_change_stmts(
             });
         } else if line.trim() == end_delimiter {
             if in_statement {
-                result.insert(
+                let mut db_object = DatabaseObject::new(
                     change_name.clone(),
-                    DatabaseObject::new(
-                        change_name.clone(),
-                        value.trim().to_string(),
-                        dependencies.clone(),
-                        properties.clone(),
-                        None,
-                    ),
+                    value.trim().to_string(),
+                    dependencies.clone(),
+                    properties.clone(),
+                    None,
                 );
+                db_object.line = start_line;
+                // The rollback is not part of the change value, so it is not part of its hash
+                if !rollback.trim().is_empty() {
+                    db_object.rollback_content = Some(rollback.trim().to_string());
+                    db_object.rollback_line = rollback_start_line;
+                }
+                result.insert(change_name.clone(), db_object);
                 dependencies.insert(change_name.clone());
                 value.clear();
+                rollback.clear();
                 properties.clear();
                 in_statement = false;
+                in_rollback = false;
             } else {
                 change_name = format!("root{}", root_counter);
                 root_counter += 1;
-                result.insert(
+                let mut db_object = DatabaseObject::new(
                     change_name.clone(),
-                    DatabaseObject::new(
-                        change_name.clone(),
-                        value.trim().to_string(),
-                        dependencies.clone(),
-                        properties.clone(),
-                        None,
-                    ),
+                    value.trim().to_string(),
+                    dependencies.clone(),
+                    properties.clone(),
+                    None,
                 );
+                db_object.line = start_line;
+                result.insert(change_name.clone(), db_object);
                 dependencies.insert(change_name.clone());
                 value.clear();
             }
-        } else if in_statement {
-            value.push_str(line);
-            value.push('\n');
+        } else if in_rollback {
+            if rollback.trim().is_empty() && !line.trim().is_empty() {
+                rollback_start_line = index + 1;
+            }
+            rollback.push_str(line);
+            rollback.push('\n');
         } else {
+            if value.trim().is_empty() && !line.trim().is_empty() {
+                start_line = index + 1;
+            }
             value.push_str(line);
             value.push('\n');
         }
@@ -403,16 +783,15 @@ fn parse_change_stmts(
 
     if !value.trim().is_empty() {
         change_name = format!("root{}", root_counter);
-        result.insert(
+        let mut db_object = DatabaseObject::new(
             change_name.clone(),
-            DatabaseObject::new(
-                change_name,
-                value.trim().to_string(),
-                dependencies,
-                properties,
-                None,
-            ),
+            value.trim().to_string(),
+            dependencies,
+            properties,
+            None,
         );
+        db_object.line = start_line;
+        result.insert(change_name, db_object);
     }
 
     result
@@ -428,6 +807,13 @@ fn parse_change_stmts(
 /// represents a dependency between two objects. It then performs a topological sort on this graph to
 /// determine the order in which the objects should be processed to respect their dependencies.
 ///
+/// The dependencies of a change are the previous changes of its file, the ones declared with the
+/// `depends` attribute and the objects its statements reference (see `dependency_edges`).
+///
+/// Every object is part of the result, including the objects without dependencies. When several
+/// objects can come next, they are taken in source order (file, then line of the change) and then
+/// by key, so the same source code always gives the same order.
+///
 /// # Arguments
 ///
 /// * `object_info` - A reference to an `IndexMap` where the keys are the names of the `DatabaseObject`
@@ -444,42 +830,48 @@ fn parse_change_stmts(
 ///
 /// # Errors
 ///
-/// This function will return an error if a cycle is detected in the dependencies, as this would make it
-/// impossible to determine a valid execution order.
+/// This function will return an error if a dependency does not match any change or matches several
+/// ones, or if a cycle is detected in the dependencies, as this would make it impossible to
+/// determine a valid execution order.
 fn determine_execution_order(
     object_info: &IndexMap<String, DatabaseObject>,
 ) -> Result<IndexMap<String, DatabaseObject>, Box<dyn std::error::Error>> {
-    let mut edges = Vec::new();
-
-    for (key, obj) in object_info {
-        for dep in &obj.dependencies {
-            // Find the full key for the dependency, considering both object name and change name
-            if let Some(dep_key) = object_info
-                .keys()
-                .find(|k| k.ends_with(dep) || k.split('.').any(|part| part == dep))
-            {
-                edges.push((dep_key.as_str(), key.as_str()));
-            } else {
-                log::warn!("Dependency '{}' not found for object '{}'", dep, key);
-            }
-        }
-    }
+    let edges = dependency_edges(object_info)?;
 
-    let order: Vec<String> = if edges.is_empty() {
-        // If there are no edges, return the keys in their original order
-        object_info.keys().cloned().collect()
-    } else {
-        topo_sort(&edges)
-            .map_err(|_| "Cycle detected in dependencies")?
-            .into_iter()
-            .map(|s| s.to_string())
-            .collect()
-    };
+    // Ties are broken by source order (file, then line), then by key, so the order is reproducible
+    let mut nodes: Vec<&str> = object_info.keys().map(String::as_str).collect();
+    nodes.sort_by(|a, b| {
+        let (obj_a, obj_b) = (&object_info[*a], &object_info[*b]);
+        Path::new(&obj_a.file_path)
+            .cmp(Path::new(&obj_b.file_path))
+            .then(obj_a.line.cmp(&obj_b.line))
+            .then(a.cmp(b))
+    });
+
+    let pairs: Vec<(&str, &str)> = edges
+        .iter()
+        .map(|edge| (edge.dependency, edge.dependent))
+        .collect();
+    let order: Vec<String> = topo_sort(&nodes, &pairs)
+        .map_err(
+            |TopologicalSortError::CycleDetected { components, cycle }| {
+                describe_cycle(object_info, &edges, &components, &cycle)
+            },
+        )?
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
 
     let mut ordered_object_info = IndexMap::new();
     for key in order {
         if let Some(obj) = object_info.get(&key) {
-            ordered_object_info.insert(key.clone(), obj.clone());
+            let mut obj = obj.clone();
+            for edge in edges.iter().filter(|edge| edge.dependent == key) {
+                if !obj.dependency_keys.iter().any(|dep| dep == edge.dependency) {
+                    obj.dependency_keys.push(edge.dependency.to_string());
+                }
+            }
+            ordered_object_info.insert(key.clone(), obj);
         }
     }
     Ok(ordered_object_info)
@@ -500,7 +892,7 @@ mod tests {
         let mut file = File::create(&file_path).unwrap();
         writeln!(file, "CREATE TABLE table1 (id INT);").unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_ok());
         let object_info = result.unwrap();
         assert_eq!(object_info.len(), 1);
@@ -509,7 +901,7 @@ mod tests {
 
     #[test]
     fn test_read_source_code_with_invalid_directory() {
-        let result = read_source_code("/invalid/path");
+        let result = read_source_code("/invalid/path", None);
         assert!(result.is_err());
     }
 
@@ -525,7 +917,7 @@ mod tests {
         writeln!(file1, "CREATE TABLE table1 (id INT);").unwrap();
         writeln!(file2, "CREATE VIEW view1 AS SELECT * FROM table1;").unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_ok());
         let object_info = result.unwrap();
         assert_eq!(object_info.len(), 2);
@@ -569,7 +961,7 @@ mod tests {
         )
         .unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_ok());
         let object_info = result.unwrap();
         assert_eq!(object_info.len(), 4);
@@ -602,7 +994,7 @@ mod tests {
         )
         .unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_err());
         let error_message = result.unwrap_err().to_string();
         assert!(error_message
@@ -621,7 +1013,7 @@ mod tests {
         )
         .unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_err());
         let error_message = result.unwrap_err().to_string();
         assert!(error_message
@@ -649,7 +1041,7 @@ mod tests {
         )
         .unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_err());
         let error_message = result.unwrap_err().to_string();
         assert!(error_message.contains("Cycle detected in dependencies"));
@@ -684,12 +1076,54 @@ mod tests {
         )
         .unwrap();
 
-        let result = read_source_code(dir.path().to_str().unwrap());
+        let result = read_source_code(dir.path().to_str().unwrap(), None);
         assert!(result.is_err());
         let error_message = result.unwrap_err().to_string();
         assert!(error_message.contains("Cycle detected in dependencies"));
     }
 
+    #[test]
+    fn test_cycle_is_reported_with_its_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let view_path = dir.path().join("schema1/view/view_a.sql");
+        let function_path = dir.path().join("schema1/function/func_b.sql");
+        fs::create_dir_all(view_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(function_path.parent().unwrap()).unwrap();
+        fs::write(&view_path, "CREATE VIEW view_a AS SELECT func_b();\nGO").unwrap();
+        fs::write(
+            &function_path,
+            "//// CHANGE name=change0 depends=view_a\nCREATE FUNCTION func_b() RETURNS integer LANGUAGE sql AS 'SELECT 1';\nGO",
+        )
+        .unwrap();
+
+        let error = read_source_code(dir.path().to_str().unwrap(), None)
+            .unwrap_err()
+            .to_string();
+        let lines: Vec<&str> = error.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Cycle detected in dependencies: schema1.function.func_b.change0 -> schema1.view.view_a.root0 -> schema1.function.func_b.change0"
+        );
+        assert_eq!(
+            lines[1],
+            format!(
+                "  schema1.function.func_b.change0 ({}:2) depends on schema1.view.view_a.root0: depends=view_a",
+                function_path.display()
+            )
+        );
+        assert_eq!(
+            lines[2],
+            format!(
+                "  schema1.view.view_a.root0 ({}:1) depends on schema1.function.func_b.change0: reference to 'func_b'",
+                view_path.display()
+            )
+        );
+        assert_eq!(
+            lines[3],
+            "Changes that depend on each other: schema1.function.func_b.change0, schema1.view.view_a.root0"
+        );
+    }
+
     #[test]
     fn test_parse_change_stmts_with_delimiters() {
         let content = "//// CHANGE name=statement1 depends=statement2\nCREATE TABLE table1 (id INT);\nGO\n//// CHANGE name=statement2\nCREATE TABLE table2 (id INT);\nGO\n";
@@ -699,6 +1133,26 @@ mod tests {
         assert!(parsed_stmts.contains_key("statement2"));
     }
 
+    #[test]
+    fn test_parse_change_stmts_with_rollback() {
+        let content = "//// CHANGE name=change0\nCREATE TABLE table1 (id INT);\n//// ROLLBACK\nDROP TABLE table1;\nGO\n//// CHANGE name=change1\nALTER TABLE table1 ADD COLUMN name TEXT;\nGO\n";
+        let parsed_stmts = parse_change_stmts(content, "//// CHANGE", "GO", "name");
+        assert_eq!(parsed_stmts.len(), 2);
+        assert_eq!(
+            parsed_stmts["change0"].value,
+            "CREATE TABLE table1 (id INT);"
+        );
+        assert_eq!(
+            parsed_stmts["change0"].rollback_content.as_deref(),
+            Some("DROP TABLE table1;")
+        );
+        assert_eq!(
+            parsed_stmts["change0"].content_hash,
+            content_hash("CREATE TABLE table1 (id INT);")
+        );
+        assert_eq!(parsed_stmts["change1"].rollback_content, None);
+    }
+
     #[test]
     fn test_parse_change_stmts_without_start_delimiter() {
         let content = "CREATE TABLE table1 (id INT);\nGO\nCREATE TABLE table2 (id INT);\nGO\n";
@@ -736,8 +1190,271 @@ mod tests {
 
     #[test]
     fn test_read_source_code_with_one_schema() {
-        let source_code = read_source_code("tests/schemas/baseline/").unwrap();
-        assert_eq!(source_code.len(), 11);
+        let source_code = read_source_code("tests/schemas/baseline/", None).unwrap();
+        assert_eq!(source_code.len(), 20);
         assert!(source_code.contains_key("baseline.function.func_with_overload.root0"));
     }
+
+    #[test]
+    fn test_execution_order_keeps_every_object_and_is_deterministic() {
+        let source_code = read_source_code("tests/schemas/baseline/", None).unwrap();
+        let keys: Vec<&String> = source_code.keys().collect();
+        assert_eq!(keys[0], "baseline.function.func1.root0");
+        assert_eq!(keys[19], "baseline.view.view1.root0");
+        let position = |key: &str| keys.iter().position(|k| *k == key).unwrap();
+        assert!(
+            position("baseline.table.table_a.change0") < position("baseline.table.table_a.change1")
+        );
+        for _ in 0..5 {
+            let again = read_source_code("tests/schemas/baseline/", None).unwrap();
+            assert_eq!(again.keys().collect::<Vec<_>>(), keys);
+        }
+    }
+
+    #[test]
+    fn test_references_are_inferred_from_the_statements() {
+        let source_code = read_source_code("tests/schemas/baseline/", None).unwrap();
+        let inferred = |key: &str| &source_code[key].inferred_dependencies;
+        assert!(
+            inferred("baseline.view.view1.root0").contains(&Reference::new(
+                "metadata_test_table",
+                ReferenceKind::Relation
+            ))
+        );
+        assert!(inferred("baseline.table.table_b_with_fk.change2")
+            .contains(&Reference::new("table_a", ReferenceKind::Relation)));
+        assert!(
+            inferred("baseline.table.table_generated_id.change1").contains(&Reference::new(
+                "table_generated_id_gen_id_seq",
+                ReferenceKind::Relation
+            ))
+        );
+    }
+
+    #[test]
+    fn test_inferred_references_order_the_changes() {
+        let dir = tempdir().unwrap();
+        let files = [
+            (
+                "schema1/table/a_child.sql",
+                "//// CHANGE name=change0\nCREATE TABLE a_child (id INT, parent_id INT REFERENCES b_parent (id));\nGO",
+            ),
+            (
+                "schema1/table/b_parent.sql",
+                "//// CHANGE name=change0\nCREATE TABLE b_parent (id INT);\nGO\n//// CHANGE name=change1\nALTER TABLE b_parent ADD COLUMN seq_id INT DEFAULT nextval('schema1.z_seq'::regclass);\nGO",
+            ),
+            (
+                "schema1/table/z_seq.sql",
+                "//// CHANGE name=change0\nCREATE SEQUENCE z_seq;\nGO",
+            ),
+            (
+                "schema1/view/a_view.sql",
+                "CREATE VIEW a_view AS WITH recent AS (SELECT * FROM b_view) SELECT * FROM recent;\nGO",
+            ),
+            (
+                "schema1/view/b_view.sql",
+                "CREATE VIEW b_view AS SELECT c_func(id) FROM a_child JOIN pg_class ON true;\nGO",
+            ),
+            (
+                "schema1/function/c_func.sql",
+                "CREATE FUNCTION c_func(integer) RETURNS integer LANGUAGE sql AS 'SELECT 1';\nGO",
+            ),
+        ];
+        for (path, content) in files {
+            let file_path = dir.path().join(path);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, content).unwrap();
+        }
+
+        let source_code = read_source_code(dir.path().to_str().unwrap(), None).unwrap();
+        let a_view = &source_code["schema1.view.a_view.root0"];
+        assert!(a_view
+            .inferred_dependencies
+            .contains(&Reference::new("b_view", ReferenceKind::Relation)));
+        assert!(!a_view
+            .inferred_dependencies
+            .contains(&Reference::new("recent", ReferenceKind::Relation)));
+        let b_view = &source_code["schema1.view.b_view.root0"];
+        assert!(b_view
+            .inferred_dependencies
+            .contains(&Reference::new("c_func", ReferenceKind::Function)));
+        assert!(!b_view
+            .inferred_dependencies
+            .contains(&Reference::new("c_func", ReferenceKind::Relation)));
+        assert_eq!(a_view.dependency_keys, vec!["schema1.view.b_view.root0"]);
+        assert_eq!(
+            source_code.keys().collect::<Vec<_>>(),
+            vec![
+                "schema1.function.c_func.root0",
+                "schema1.table.b_parent.change0",
+                "schema1.table.z_seq.change0",
+                "schema1.table.b_parent.change1",
+                "schema1.table.a_child.change0",
+                "schema1.view.b_view.root0",
+                "schema1.view.a_view.root0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_hash_ignores_reformatting() {
+        let original =
+            "//// CHANGE name=change0\nCREATE TABLE table1 (\n    id INT,\n    name TEXT\n);\nGO";
+        let reformatted = "//// CHANGE name=change0\n-- Reformatted\nCREATE TABLE table1 (id INT, name TEXT);\nGO";
+        let modified = "//// CHANGE name=change0\nCREATE TABLE table1 (id BIGINT, name TEXT);\nGO";
+
+        let hash = |content: &str| {
+            parse_change_stmts(content, "//// CHANGE", "GO", "name")["change0"]
+                .content_hash
+                .clone()
+        };
+        assert_eq!(hash(original), hash(reformatted));
+        assert_ne!(hash(original), hash(modified));
+    }
+
+    #[test]
+    fn test_tokens_are_resolved_from_environment_variables() {
+        let dir = tempdir().unwrap();
+        let table_dir = dir.path().join("schema1").join("table");
+        fs::create_dir_all(&table_dir).unwrap();
+        let mut file = File::create(table_dir.join("table1.sql")).unwrap();
+        write!(
+            file,
+            "//// CHANGE name=change0\nCREATE TABLE table1 (id INT);\nGO\n//// CHANGE name=change1\n\nALTER TABLE table1\n    OWNER TO ${{owner}};\nGO"
+        )
+        .unwrap();
+        let base_dir = dir.path().to_str().unwrap();
+
+        let mut environment = Environment {
+            name: "DEV".to_string(),
+            ..Default::default()
+        };
+        environment
+            .variables
+            .insert("owner".to_string(), "dev_owner".to_string());
+        let dev = read_source_code(base_dir, Some(&environment)).unwrap();
+        environment
+            .variables
+            .insert("owner".to_string(), "prod_owner".to_string());
+        let prod = read_source_code(base_dir, Some(&environment)).unwrap();
+
+        let key = "schema1.table.table1.change1";
+        assert_eq!(
+            dev[key].value,
+            "ALTER TABLE table1\n    OWNER TO dev_owner;"
+        );
+        assert_eq!(
+            prod[key].value,
+            "ALTER TABLE table1\n    OWNER TO prod_owner;"
+        );
+        assert_eq!(dev[key].content_hash, prod[key].content_hash);
+
+        let error = read_source_code(base_dir, None).unwrap_err().to_string();
+        assert!(error.contains("Unresolved token '${owner}'"));
+        assert!(error.contains("table1.sql:7"));
+    }
+
+    #[test]
+    fn test_unresolved_tokens_of_rollbacks_report_their_line() {
+        let dir = tempdir().unwrap();
+        let table_dir = dir.path().join("schema1").join("table");
+        fs::create_dir_all(&table_dir).unwrap();
+        fs::write(
+            table_dir.join("table1.sql"),
+            "//// CHANGE name=change0\nCREATE TABLE table1 (id INT);\n//// ROLLBACK\n\nDROP TABLE ${old_schema}.table1;\nGO",
+        )
+        .unwrap();
+
+        let error = read_source_code(dir.path().to_str().unwrap(), None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unresolved token '${old_schema}'"));
+        assert!(error.ends_with("table1.sql:5"));
+    }
+
+    #[test]
+    fn test_changes_of_other_environments_are_not_read() {
+        let dir = tempdir().unwrap();
+        let files = [
+            (
+                "schema1/table/table1.sql",
+                "//// CHANGE name=change0\nCREATE TABLE table1 (id INT);\nGO\n//// CHANGE name=change1 includeEnvs=PROD\nGRANT SELECT ON table1 TO ${prod_reader};\nGO",
+            ),
+            (
+                "schema2/table/table2.sql",
+                "//// CHANGE name=change0\nCREATE TABLE table2 (id ${unknown_type});\nGO",
+            ),
+        ];
+        for (path, content) in files {
+            let file_path = dir.path().join(path);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, content).unwrap();
+        }
+
+        let environment = Environment {
+            name: "DEV".to_string(),
+            schemas: vec!["schema1".to_string()],
+            ..Default::default()
+        };
+        let source_code =
+            read_source_code(dir.path().to_str().unwrap(), Some(&environment)).unwrap();
+        assert_eq!(
+            source_code.keys().collect::<Vec<_>>(),
+            vec!["schema1.table.table1.change0"]
+        );
+    }
+
+    #[test]
+    fn test_changes_are_parsed_with_the_dialect_of_the_environment() {
+        let dir = tempdir().unwrap();
+        let table_dir = dir.path().join("schema1").join("table");
+        fs::create_dir_all(&table_dir).unwrap();
+        let mut file = File::create(table_dir.join("table1.sql")).unwrap();
+        write!(
+            file,
+            "//// CHANGE name=change0\nCREATE TABLE `table1` (id INT AUTO_INCREMENT PRIMARY KEY);\nGO"
+        )
+        .unwrap();
+        let base_dir = dir.path().to_str().unwrap();
+
+        let mut environment = Environment {
+            name: "DEV".to_string(),
+            connection: "mysql://root@localhost/app".to_string(),
+            ..Default::default()
+        };
+        let source_code = read_source_code(base_dir, Some(&environment)).unwrap();
+        assert!(source_code.contains_key("schema1.table.table1.change0"));
+
+        environment.dialect = Some(SqlDialect::PostgreSql);
+        let error = read_source_code(base_dir, Some(&environment))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Failed to parse change 'change0'"));
+        assert!(error.contains("table1.sql:2 with the PostgreSQL dialect"));
+    }
+
+    #[test]
+    fn test_content_hash_of_parsed_statements() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("schema1").join("table").join("table1.sql");
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+
+        let mut hashes = Vec::new();
+        for content in [
+            "//// CHANGE name=change0 hash=ast\nCREATE TABLE table1 (id INT);\nGO",
+            "//// CHANGE name=change0 hash=ast\ncreate table table1 (id int);\nGO",
+            "//// CHANGE name=change0\ncreate table table1 (id int);\nGO",
+        ] {
+            let mut file = File::create(&file_path).unwrap();
+            writeln!(file, "{}", content).unwrap();
+            let object_info = read_source_code(dir.path().to_str().unwrap(), None).unwrap();
+            hashes.push(
+                object_info["schema1.table.table1.change0"]
+                    .content_hash
+                    .clone(),
+            );
+        }
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[1], hashes[2]);
+    }
 }
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::changeset::{ChangeAction, ChangeSet, ChangeStatus};
+use crate::deploy_log::DeployExecution;
+
+/// The number of changes of an object type in each state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    /// Changes of the source code that are deployed with the same hash.
+    pub applied: usize,
+    /// Changes of the source code that are not deployed yet.
+    pub pending: usize,
+    /// Changes of the source code whose hash differs from the deployed one.
+    pub modified: usize,
+    /// Changes of the deploy log that are no longer in the source code.
+    pub orphaned: usize,
+}
+
+/// A summary of what is deployed in an environment compared with the source code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// The counts of changes per `(schema, object type)`, sorted by schema and object type.
+    pub counts: BTreeMap<(String, String), StatusCounts>,
+    /// The last row of the `deploy_execution` table.
+    pub last_execution: Option<DeployExecution>,
+    /// The `last_version` setting of the `deploy_log_config` table.
+    pub last_version: Option<String>,
+}
+
+impl Status {
+    /// Summarizes a changeset computed between the source code and the deploy log.
+    ///
+    /// # Arguments
+    ///
+    /// * `changeset` - The changeset with every change of the source code and the deploy log.
+    /// * `last_execution` - The last deploy execution of the environment, if any.
+    /// * `last_version` - The last version of oxigration that deployed the environment, if any.
+    pub fn compute(
+        changeset: &ChangeSet,
+        last_execution: Option<DeployExecution>,
+        last_version: Option<String>,
+    ) -> Self {
+        let mut counts: BTreeMap<(String, String), StatusCounts> = BTreeMap::new();
+        for change in &changeset.changes {
+            // A recreated change is listed twice, its drop is only counted with its deployment
+            if change.cause.is_some() && change.action == ChangeAction::Drop {
+                continue;
+            }
+            let entry = counts
+                .entry((
+                    change.object.schema_name.clone(),
+                    change.object.object_type.clone(),
+                ))
+                .or_default();
+            match change.status {
+                ChangeStatus::Unchanged => entry.applied += 1,
+                ChangeStatus::New => entry.pending += 1,
+                ChangeStatus::Modified => entry.modified += 1,
+                ChangeStatus::Removed => entry.orphaned += 1,
+            }
+        }
+
+        Status {
+            counts,
+            last_execution,
+            last_version,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let schema_width = self
+            .counts
+            .keys()
+            .map(|(schema, _)| schema.len())
+            .chain(["Schema".len()])
+            .max()
+            .unwrap_or_default();
+        let type_width = self
+            .counts
+            .keys()
+            .map(|(_, object_type)| object_type.len())
+            .chain(["Object type".len()])
+            .max()
+            .unwrap_or_default();
+
+        writeln!(
+            f,
+            "{:<schema_width$}  {:<type_width$}  {:>7}  {:>7}  {:>8}  {:>8}",
+            "Schema", "Object type", "Applied", "Pending", "Modified", "Orphaned"
+        )?;
+        for ((schema, object_type), counts) in &self.counts {
+            writeln!(
+                f,
+                "{:<schema_width$}  {:<type_width$}  {:>7}  {:>7}  {:>8}  {:>8}",
+                schema,
+                object_type,
+                counts.applied,
+                counts.pending,
+                counts.modified,
+                counts.orphaned
+            )?;
+        }
+
+        writeln!(f)?;
+        match &self.last_execution {
+            Some(execution) => {
+                writeln!(
+                    f,
+                    "Last deploy execution: #{} {} (requested by {}, executed by {}, version {})",
+                    execution.id,
+                    execution.status,
+                    execution.requester,
+                    execution.executor,
+                    execution.product_version
+                )?;
+                writeln!(
+                    f,
+                    "  started {}, completed {}",
+                    execution.time_started.as_deref().unwrap_or("-"),
+                    execution.time_completed.as_deref().unwrap_or("-")
+                )?;
+                if let Some(reason) = &execution.reason {
+                    writeln!(f, "  reason: {}", reason)?;
+                }
+            }
+            None => writeln!(f, "Last deploy execution: none")?,
+        }
+        writeln!(
+            f,
+            "Last version: {}",
+            self.last_version.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::tests::{object, objects};
+
+    #[test]
+    fn test_status_counts_per_schema_and_type() {
+        let source = objects(vec![
+            object("s.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("s.table.t1.change1", "ALTER TABLE t1 ADD COLUMN a INT;"),
+            object("s.table.t2.change0", "CREATE TABLE t2 (id INT);"),
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 2;"),
+        ]);
+        let log = objects(vec![
+            object("s.table.t1.change0", "CREATE TABLE t1 (id INT);"),
+            object("s.table.t1.change1", "ALTER TABLE t1 ADD COLUMN b INT;"),
+            object("s.view.v1.root0", "CREATE VIEW v1 AS SELECT 1;"),
+            object("s.view.v2.root0", "CREATE VIEW v2 AS SELECT 1;"),
+        ]);
+        let changeset = ChangeSet::compute(&source, &log);
+
+        let status = Status::compute(&changeset, None, Some("0.1.0".to_string()));
+
+        assert_eq!(
+            status.counts[&("s".to_string(), "table".to_string())],
+            StatusCounts {
+                applied: 1,
+                pending: 1,
+                modified: 1,
+                orphaned: 0
+            }
+        );
+        assert_eq!(
+            status.counts[&("s".to_string(), "view".to_string())],
+            StatusCounts {
+                applied: 0,
+                pending: 0,
+                modified: 1,
+                orphaned: 1
+            }
+        );
+
+        let report = status.to_string();
+        assert!(report.contains("Last deploy execution: none"));
+        assert!(report.ends_with("Last version: 0.1.0\n"));
+    }
+}
@@ -1,19 +1,183 @@
 pub mod topsort;
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use core::ops::ControlFlow;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlparser::ast::{
+    AlterTableOperation, ColumnDef, ColumnOption, Expr, Ident, ObjectName, Statement,
+    TableConstraint, Visit, Visitor,
+};
+use sqlparser::dialect::{
+    Dialect, GenericDialect, MsSqlDialect, MySqlDialect, PostgreSqlDialect, SQLiteDialect,
+};
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, TokenWithLocation, Tokenizer};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 
-pub static SCHEMA_SUPPORT: AtomicBool = AtomicBool::new(false);
+/// The schema of the deploy log tables when the environment does not set `metadata_schema`.
+pub const DEFAULT_METADATA_SCHEMA: &str = "oxigration";
+
+/// Where the deploy log tables of a target database are and the syntax to query them with.
+///
+/// The context is detected once per command with `deploy_log::metadata_context` and passed to
+/// every query of the deploy log tables, so that connections to different databases in the same
+/// process do not share it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataContext {
+    /// The kind of the target database, which decides the syntax of the queries.
+    pub kind: DatabaseKind,
+    /// The schema of the deploy log tables.
+    pub schema: String,
+    /// Whether the target database supports schemas, the deploy log tables are not qualified
+    /// otherwise.
+    pub supports_schemas: bool,
+}
+
+impl MetadataContext {
+    /// Returns the prefix qualifying the deploy log tables, such as `oxigration.`, or an empty
+    /// string if the target database does not support schemas.
+    pub fn schema_prefix(&self) -> String {
+        if self.supports_schemas {
+            format!("{}.", self.schema)
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// The database management systems supported by oxigration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseKind {
+    PostgreSql,
+    MySql,
+    Sqlite,
+}
+
+impl DatabaseKind {
+    /// Determines the kind of database from the scheme of a connection string.
+    ///
+    /// # Arguments
+    ///
+    /// * `connection_string` - A string slice that holds the connection string to the database.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result`:
+    /// * `Ok(DatabaseKind)` if the scheme is supported.
+    /// * `Err(Box<dyn Error>)` if the scheme is unknown.
+    pub fn from_connection_string(connection_string: &str) -> Result<Self, Box<dyn Error>> {
+        let scheme = connection_string
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match scheme.as_str() {
+            "postgres" | "postgresql" => Ok(DatabaseKind::PostgreSql),
+            "mysql" | "mariadb" => Ok(DatabaseKind::MySql),
+            "sqlite" => Ok(DatabaseKind::Sqlite),
+            _ => Err(format!("Unsupported database '{}' in connection string", scheme).into()),
+        }
+    }
+}
+
+impl fmt::Display for DatabaseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DatabaseKind::PostgreSql => "postgresql",
+            DatabaseKind::MySql => "mysql",
+            DatabaseKind::Sqlite => "sqlite",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The SQL dialects the changes of the source code are parsed with.
+///
+/// The dialect of an environment is its `dialect` setting in `oxigration.toml`, or the one of the
+/// scheme of its connection string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    #[serde(alias = "postgres")]
+    PostgreSql,
+    #[serde(alias = "mariadb")]
+    MySql,
+    Sqlite,
+    #[serde(alias = "sqlserver")]
+    MsSql,
+    Generic,
+}
+
+impl SqlDialect {
+    /// Determines the SQL dialect from the scheme of a connection string.
+    ///
+    /// # Arguments
+    ///
+    /// * `connection_string` - A string slice that holds the connection string to the database.
+    ///
+    /// # Returns
+    ///
+    /// This function returns the dialect of the scheme, or `SqlDialect::Generic` if the scheme is
+    /// not known.
+    pub fn from_connection_string(connection_string: &str) -> Self {
+        let scheme = connection_string
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match scheme.as_str() {
+            "postgres" | "postgresql" => SqlDialect::PostgreSql,
+            "mysql" | "mariadb" => SqlDialect::MySql,
+            "sqlite" => SqlDialect::Sqlite,
+            "mssql" | "sqlserver" => SqlDialect::MsSql,
+            _ => SqlDialect::Generic,
+        }
+    }
+
+    /// Returns the sqlparser dialect used to parse the changes.
+    pub fn parser_dialect(&self) -> Box<dyn Dialect> {
+        match self {
+            SqlDialect::PostgreSql => Box::new(PostgreSqlDialect {}),
+            SqlDialect::MySql => Box::new(MySqlDialect {}),
+            SqlDialect::Sqlite => Box::new(SQLiteDialect {}),
+            SqlDialect::MsSql => Box::new(MsSqlDialect {}),
+            SqlDialect::Generic => Box::new(GenericDialect {}),
+        }
+    }
+}
+
+impl fmt::Display for SqlDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SqlDialect::PostgreSql => "PostgreSQL",
+            SqlDialect::MySql => "MySQL",
+            SqlDialect::Sqlite => "SQLite",
+            SqlDialect::MsSql => "MSSQL",
+            SqlDialect::Generic => "generic",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 /// Formats a query template by replacing the `{schema_prefix}` placeholder with the appropriate schema prefix.
 ///
 /// This function is useful for dynamically generating SQL queries that need to be compatible with databases
 /// that may or may not support schemas. If schema support is enabled, the `{schema_prefix}` placeholder in the
-/// query template will be replaced with the schema name (e.g., "oxigration."). If schema support is not enabled,
+/// query template will be replaced with the metadata schema name (e.g., "oxigration."). If schema support is not enabled,
 /// the placeholder will be replaced with an empty string.
 ///
+/// Query templates are written in the syntax of PostgreSQL, which SQLite also accepts, and are translated
+/// for the kind of the target database of the metadata context:
+/// * The `{text}` placeholder is replaced with the type to cast values to text, `TEXT` or `CHAR` in MySQL.
+/// * In MySQL, `"quoted"` identifiers are quoted with backticks and the `$1`, `$2`, ... bind markers
+///   are replaced with `?`, so the markers must appear in the order of the bound values.
+///
 /// # Arguments
 ///
 /// * `query_template` - A string slice that holds the SQL query template containing the `{schema_prefix}` placeholder.
+/// * `metadata` - The kind of the target database and the schema of its deploy log tables.
 ///
 /// # Returns
 ///
@@ -24,15 +188,523 @@ pub static SCHEMA_SUPPORT: AtomicBool = AtomicBool::new(false);
 ///
 /// ```
 /// let query_template = "SELECT * FROM {schema_prefix}deploy_log;";
-/// let formatted_query = crate::deploy_log::format_query_with_schema(query_template);
+/// let formatted_query = crate::utils::format_query_with_schema(query_template, &metadata);
 /// // If schema support is enabled, `formatted_query` will be "SELECT * FROM oxigration.deploy_log;"
 /// // If schema support is not enabled, `formatted_query` will be "SELECT * FROM deploy_log;"
 /// ```
-pub fn format_query_with_schema(query_template: &str) -> String {
-    let schema_prefix = if SCHEMA_SUPPORT.load(Ordering::Relaxed) {
-        "oxigration."
-    } else {
-        ""
+pub fn format_query_with_schema(query_template: &str, metadata: &MetadataContext) -> String {
+    let query = query_template.replace("{schema_prefix}", &metadata.schema_prefix());
+    translate_query(&query, metadata.kind)
+}
+
+/// Translates a query written in the syntax of PostgreSQL for a kind of database, as described in
+/// `format_query_with_schema`.
+fn translate_query(query: &str, kind: DatabaseKind) -> String {
+    if kind != DatabaseKind::MySql {
+        return query.replace("{text}", "TEXT");
+    }
+    let query = query.replace("{text}", "CHAR");
+    let mut translated = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => translated.push('`'),
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.peek().is_some_and(char::is_ascii_digit) {
+                    chars.next();
+                }
+                translated.push('?');
+            }
+            _ => translated.push(c),
+        }
+    }
+    translated
+}
+
+/// Replaces the logical schema names that qualify the object names of SQL statements with their
+/// physical schema names.
+///
+/// The statements are parsed with the SQL dialect of the environment to find the object names
+/// whose schema qualifier is mapped: the relations of queries, DML and DDL statements, the names
+/// of created and dropped objects, the tables of `REFERENCES` clauses, the called functions and
+/// the table and function of triggers. A bare schema name is compared without case and a quoted
+/// one exactly, and stays quoted. Only these qualifiers are replaced in the text of the
+/// statements, located with the tokenizer, so the rest of the text is executed as written.
+/// Aliases, column qualifiers, comments and string literals are kept, so the body of a function,
+/// which is a string for the parser, is not mapped.
+///
+/// # Arguments
+///
+/// * `sql` - A string slice that holds the SQL statements.
+/// * `schema_mapping` - The physical schema name of each logical schema name.
+/// * `dialect` - The SQL dialect the statements are parsed with.
+///
+/// # Returns
+///
+/// This function returns a `Result`:
+/// * `Ok(String)` with the mapped statements.
+/// * `Err(Box<dyn Error>)` if the statements cannot be parsed.
+pub fn map_schema_names(
+    sql: &str,
+    schema_mapping: &HashMap<String, String>,
+    dialect: SqlDialect,
+) -> Result<String, Box<dyn Error>> {
+    if schema_mapping.is_empty() {
+        return Ok(sql.to_string());
+    }
+
+    let parser_dialect = dialect.parser_dialect();
+    let statements = Parser::parse_sql(parser_dialect.as_ref(), sql)?;
+    let mut finder = SchemaNameFinder {
+        schema_mapping,
+        names: HashMap::new(),
     };
-    query_template.replace("{schema_prefix}", schema_prefix)
+    for statement in &statements {
+        let _ = statement.visit(&mut finder);
+    }
+    if finder.names.is_empty() {
+        return Ok(sql.to_string());
+    }
+
+    let tokens = Tokenizer::new(parser_dialect.as_ref(), sql).tokenize_with_location()?;
+    let offsets = token_offsets(sql, &tokens);
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&index| !matches!(tokens[index].token, Token::Whitespace(_)))
+        .collect();
+
+    let mut mapped = String::with_capacity(sql.len());
+    let mut copied = 0;
+    let mut position = 0;
+    while position < significant.len() {
+        // An object name is a run of words separated by periods
+        let mut parts: Vec<Ident> = Vec::new();
+        let mut indexes: Vec<usize> = Vec::new();
+        while let Some(&index) = significant.get(position) {
+            let Token::Word(word) = &tokens[index].token else {
+                break;
+            };
+            parts.push(Ident {
+                value: word.value.clone(),
+                quote_style: word.quote_style,
+            });
+            indexes.push(index);
+            position += 1;
+            match significant.get(position) {
+                Some(&next) if tokens[next].token == Token::Period => position += 1,
+                _ => break,
+            }
+        }
+        if parts.is_empty() {
+            position += 1;
+            continue;
+        }
+        if let Some(physical_schema) = finder.names.get(&parts) {
+            let schema = parts.len() - 2;
+            let index = indexes[schema];
+            mapped.push_str(&sql[copied..offsets[index]]);
+            mapped.push_str(
+                &Ident {
+                    value: physical_schema.clone(),
+                    quote_style: parts[schema].quote_style,
+                }
+                .to_string(),
+            );
+            copied = offsets.get(index + 1).copied().unwrap_or(sql.len());
+        }
+    }
+    mapped.push_str(&sql[copied..]);
+    Ok(mapped)
+}
+
+/// Returns the byte offset in the SQL text of each token, from its line and column.
+fn token_offsets(sql: &str, tokens: &[TokenWithLocation]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(tokens.len());
+    let (mut line, mut column) = (1, 1);
+    for (offset, c) in sql.char_indices() {
+        while offsets.len() < tokens.len() {
+            let location = &tokens[offsets.len()].location;
+            if (location.line, location.column) != (line, column) {
+                break;
+            }
+            offsets.push(offset);
+        }
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    offsets.resize(tokens.len(), sql.len());
+    offsets
+}
+
+/// Visitor finding the object names of statements whose schema qualifier is mapped, for
+/// `map_schema_names`.
+struct SchemaNameFinder<'a> {
+    schema_mapping: &'a HashMap<String, String>,
+    /// The parts of each mapped object name, with the physical schema that replaces its qualifier.
+    names: HashMap<Vec<Ident>, String>,
+}
+impl SchemaNameFinder<'_> {
+    /// Returns the physical schema of a schema qualifier. Several logical schema names differing
+    /// only in case match a bare qualifier, the exact one or else the first in order is used.
+    fn physical_schema(&self, schema: &Ident) -> Option<String> {
+        if let Some(physical) = self.schema_mapping.get(&schema.value) {
+            return Some(physical.clone());
+        }
+        if schema.quote_style.is_some() {
+            return None;
+        }
+        self.schema_mapping
+            .iter()
+            .filter(|(logical, _)| logical.eq_ignore_ascii_case(&schema.value))
+            .min_by_key(|(logical, _)| logical.as_str())
+            .map(|(_, physical)| physical.clone())
+    }
+
+    /// Records a `schema.name` or `database.schema.name` object name whose schema is mapped.
+    fn map_name(&mut self, name: &ObjectName) {
+        let parts = name.0.len();
+        if parts < 2 {
+            return;
+        }
+        if let Some(physical) = self.physical_schema(&name.0[parts - 2]) {
+            self.names.insert(name.0.clone(), physical);
+        }
+    }
+
+    fn map_column_references(&mut self, column: &ColumnDef) {
+        for option in &column.options {
+            if let ColumnOption::ForeignKey { foreign_table, .. } = &option.option {
+                self.map_name(foreign_table);
+            }
+        }
+    }
+
+    fn map_constraint_references(&mut self, constraint: &TableConstraint) {
+        if let TableConstraint::ForeignKey { foreign_table, .. } = constraint {
+            self.map_name(foreign_table);
+        }
+    }
+}
+impl Visitor for SchemaNameFinder<'_> {
+    type Break = ();
+
+    // The names of tables, indexed tables and altered tables are relations
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        self.map_name(relation);
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if let Expr::Function(function) = expr {
+            self.map_name(&function.name);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        match statement {
+            Statement::CreateView { name, .. }
+            | Statement::CreateFunction { name, .. }
+            | Statement::CreateProcedure { name, .. }
+            | Statement::CreateSequence { name, .. } => self.map_name(name),
+            Statement::CreateTable(table) => {
+                for column in &table.columns {
+                    self.map_column_references(column);
+                }
+                for constraint in &table.constraints {
+                    self.map_constraint_references(constraint);
+                }
+            }
+            Statement::AlterTable { operations, .. } => {
+                for operation in operations {
+                    match operation {
+                        AlterTableOperation::AddColumn { column_def, .. } => {
+                            self.map_column_references(column_def);
+                        }
+                        AlterTableOperation::AddConstraint(constraint) => {
+                            self.map_constraint_references(constraint);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Statement::CreateTrigger {
+                table_name,
+                referenced_table_name,
+                exec_body,
+                ..
+            } => {
+                self.map_name(table_name);
+                if let Some(referenced_table_name) = referenced_table_name {
+                    self.map_name(referenced_table_name);
+                }
+                self.map_name(&exec_body.func_desc.name);
+            }
+            Statement::Drop { names, .. } => {
+                for name in names {
+                    self.map_name(name);
+                }
+            }
+            Statement::DropFunction { func_desc, .. }
+            | Statement::DropProcedure {
+                proc_desc: func_desc,
+                ..
+            } => {
+                for function in func_desc {
+                    self.map_name(&function.name);
+                }
+            }
+            Statement::DropTrigger { table_name, .. } => self.map_name(table_name),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Computes the hash of a change content as a hex encoded SHA-256 digest.
+///
+/// The hash is stored in the `content_hash` column of the deploy log and is used to detect
+/// changes that were added, modified or removed from the source code since they were deployed.
+/// The content is normalized with `normalize_sql` before hashing, so reformatting a change or
+/// editing its comments does not modify its hash. SHA-256 is used instead of the standard library
+/// hasher because the hash must not change between Rust versions or platforms.
+///
+/// # Arguments
+///
+/// * `content` - A string slice that holds the content of the change.
+///
+/// # Returns
+///
+/// This function returns a `String` with the hex encoded digest of the normalized content.
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(normalize_sql(content).as_bytes()))
+}
+
+/// Normalizes the text of SQL statements so that formatting differences do not matter.
+///
+/// Comments are removed, every run of whitespace is collapsed into a single space and the
+/// whitespace around punctuation (`(`, `)`, `,` and `;`) is removed. Quoted strings, quoted
+/// identifiers and dollar-quoted bodies are kept verbatim, since their content is meaningful.
+/// For example, `CREATE TABLE t1 (\n    id INT -- key\n);` is normalized to
+/// `CREATE TABLE t1(id INT);`.
+pub fn normalize_sql(content: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let mut normalized = String::with_capacity(content.len());
+    let mut pending_space = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        // Comments are dropped and behave like whitespace
+        if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            pending_space = true;
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            pending_space = true;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+
+        if pending_space
+            && !normalized.is_empty()
+            && !normalized.ends_with(['(', ')', ',', ';'])
+            && !matches!(c, '(' | ')' | ',' | ';')
+        {
+            normalized.push(' ');
+        }
+        pending_space = false;
+
+        // Quoted content is copied verbatim up to the closing quote
+        let end = match c {
+            '\'' | '"' => {
+                let mut end = i + 1;
+                while end < chars.len() && chars[end] != c {
+                    end += 1;
+                }
+                end + 1
+            }
+            '$' => match dollar_quote_tag(&chars[i..]) {
+                Some(tag) => {
+                    let mut end = i + tag.len();
+                    while end < chars.len() && !chars[end..].starts_with(&tag) {
+                        end += 1;
+                    }
+                    end + tag.len()
+                }
+                None => i + 1,
+            },
+            _ => i + 1,
+        };
+        let end = end.min(chars.len());
+        normalized.extend(&chars[i..end]);
+        i = end;
+    }
+
+    normalized
+}
+
+/// Returns the opening tag (e.g. `$$` or `$body$`) if the characters start a dollar-quoted string.
+fn dollar_quote_tag(chars: &[char]) -> Option<Vec<char>> {
+    let mut end = 1;
+    while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+        // Positional parameters such as `$1` are not dollar quotes
+        if end == 1 && chars[end].is_ascii_digit() {
+            return None;
+        }
+        end += 1;
+    }
+    if chars.get(end) == Some(&'$') {
+        Some(chars[..=end].to_vec())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_sql_ignores_formatting_and_comments() {
+        let original = "CREATE TABLE table_a (\n    a_id integer NOT NULL,\n    a2_id integer\n);";
+        let reformatted = "-- table_a definition\nCREATE TABLE table_a (a_id integer NOT NULL, /* second */ a2_id integer);\n\n";
+        assert_eq!(normalize_sql(original), normalize_sql(reformatted));
+        assert_eq!(content_hash(original), content_hash(reformatted));
+        assert_ne!(
+            content_hash(original),
+            content_hash("CREATE TABLE table_a (a_id bigint NOT NULL, a2_id integer);")
+        );
+    }
+
+    #[test]
+    fn test_normalize_sql_keeps_quoted_content() {
+        let function = "CREATE FUNCTION f() RETURNS integer AS '\nBEGIN\n    -- keep me\n    RETURN 1;\nEND;\n';";
+        assert!(normalize_sql(function).contains("-- keep me\n    RETURN 1;"));
+
+        let dollar_quoted = "CREATE FUNCTION f() RETURNS integer AS $body$ SELECT  $1 $body$;";
+        assert!(normalize_sql(dollar_quoted).contains("$body$ SELECT  $1 $body$"));
+
+        assert_eq!(
+            normalize_sql("INSERT INTO t VALUES ('a  b',   $1);"),
+            "INSERT INTO t VALUES('a  b',$1);"
+        );
+    }
+
+    #[test]
+    fn test_map_schema_names() -> Result<(), Box<dyn Error>> {
+        let schema_mapping = HashMap::from([("baseline".to_string(), "app_dev1".to_string())]);
+        let map = |sql: &str| map_schema_names(sql, &schema_mapping, SqlDialect::PostgreSql);
+
+        assert_eq!(
+            map("ALTER TABLE baseline.t1 ADD CONSTRAINT fk FOREIGN KEY (id) REFERENCES \"baseline\".t2 (id);")?,
+            "ALTER TABLE app_dev1.t1 ADD CONSTRAINT fk FOREIGN KEY (id) REFERENCES \"app_dev1\".t2 (id);"
+        );
+        assert_eq!(
+            map("CREATE VIEW BASELINE.v1 AS SELECT baseline.f(x) FROM baseline.t1 AS t;")?,
+            "CREATE VIEW app_dev1.v1 AS SELECT app_dev1.f(x) FROM app_dev1.t1 AS t;"
+        );
+        assert_eq!(
+            map("DROP VIEW IF EXISTS baseline.v1;\nDROP FUNCTION baseline.f;")?,
+            "DROP VIEW IF EXISTS app_dev1.v1;\nDROP FUNCTION app_dev1.f;"
+        );
+
+        // Aliases, column qualifiers, string literals and quoted names of another case are kept
+        let unmapped =
+            "SELECT baseline.id, 'baseline.t1' FROM t1 AS baseline JOIN \"Baseline\".t2 ON true;";
+        assert_eq!(map(unmapped)?, unmapped);
+        assert_eq!(
+            map_schema_names(
+                "CREATE TABLE baseline.t1 (id INT);",
+                &HashMap::new(),
+                SqlDialect::PostgreSql
+            )?,
+            "CREATE TABLE baseline.t1 (id INT);"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_schema_names_keeps_the_text_of_the_statements() -> Result<(), Box<dyn Error>> {
+        let schema_mapping = HashMap::from([("baseline".to_string(), "app_dev1".to_string())]);
+        let function = "-- Counts the rows of t1
+CREATE OR REPLACE FUNCTION baseline . count_t1() RETURNS bigint
+LANGUAGE plpgsql AS $body$
+DECLARE
+    total bigint;
+BEGIN
+    SELECT count(*) INTO total FROM baseline.t1; -- baseline.t1 stays
+    RETURN total;
+END;
+$body$;";
+
+        assert_eq!(
+            map_schema_names(function, &schema_mapping, SqlDialect::PostgreSql)?,
+            function.replace("baseline . count_t1", "app_dev1 . count_t1")
+        );
+        assert_eq!(
+            map_schema_names(
+                "INSERT INTO `baseline`.t1 (id) VALUES (1);\n  /* seed */ SELECT 1 FROM baseline.t1;",
+                &schema_mapping,
+                SqlDialect::MySql
+            )?,
+            "INSERT INTO `app_dev1`.t1 (id) VALUES (1);\n  /* seed */ SELECT 1 FROM app_dev1.t1;"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_query_for_each_database_kind() {
+        let template = "SELECT CAST(applied_at AS {text}) FROM deploy_log_config WHERE \"key\" = $1 AND value = $2;";
+
+        assert_eq!(
+            translate_query(template, DatabaseKind::MySql),
+            "SELECT CAST(applied_at AS CHAR) FROM deploy_log_config WHERE `key` = ? AND value = ?;"
+        );
+        assert_eq!(
+            translate_query(template, DatabaseKind::Sqlite),
+            "SELECT CAST(applied_at AS TEXT) FROM deploy_log_config WHERE \"key\" = $1 AND value = $2;"
+        );
+    }
+
+    #[test]
+    fn test_format_query_with_schema_uses_the_metadata_context() {
+        let template = "SELECT value FROM {schema_prefix}deploy_log_config WHERE \"key\" = $1;";
+        let postgres = MetadataContext {
+            kind: DatabaseKind::PostgreSql,
+            schema: "oxigration_dev".to_string(),
+            supports_schemas: true,
+        };
+        let sqlite = MetadataContext {
+            kind: DatabaseKind::Sqlite,
+            schema: DEFAULT_METADATA_SCHEMA.to_string(),
+            supports_schemas: false,
+        };
+
+        assert_eq!(
+            format_query_with_schema(template, &postgres),
+            "SELECT value FROM oxigration_dev.deploy_log_config WHERE \"key\" = $1;"
+        );
+        assert_eq!(
+            format_query_with_schema(template, &sqlite),
+            "SELECT value FROM deploy_log_config WHERE \"key\" = $1;"
+        );
+    }
 }
@@ -1,65 +1,210 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::hash::Hash;
 
 #[derive(Debug, Eq, PartialEq)]
-pub enum TopologicalSortError {
-    CycleDetected,
+pub enum TopologicalSortError<Node> {
+    /// The edges contain at least one cycle.
+    CycleDetected {
+        /// The strongly connected components of the nodes that cannot be sorted, which are the
+        /// groups of nodes that depend on each other, ordered like the nodes.
+        components: Vec<Vec<Node>>,
+        /// A shortest cycle of the first component, following the edges and starting and ending
+        /// with the same node.
+        cycle: Vec<Node>,
+    },
 }
 
-type TopoSortResult<Node> = Result<Vec<Node>, TopologicalSortError>;
+type TopoSortResult<Node> = Result<Vec<Node>, TopologicalSortError<Node>>;
 
-/// Given a directed graph represented as a list of edges (source, destination),
-/// this function uses Kahn's algorithm to return a topological sort of the graph
-/// or detect if there's a cycle.
-pub fn topo_sort<Node: Hash + Eq + Copy>(edges: &Vec<(Node, Node)>) -> TopoSortResult<Node> {
-    // Step 1: Initialize data structures
-    let mut edges_by_source: HashMap<Node, Vec<Node>> = HashMap::new();
-    let mut incoming_edges_count: HashMap<Node, usize> = HashMap::new();
+/// Given a directed graph represented as a list of nodes and a list of edges (source, destination),
+/// this function uses Kahn's algorithm to return a topological sort of the graph or detect if
+/// there's a cycle.
+///
+/// Every node is part of the result, including the nodes without any edge. Among the nodes whose
+/// dependencies are all sorted, the one that comes first in `nodes` is always taken first, so the
+/// result only depends on the order of `nodes` and never on hashing. Nodes that only appear in
+/// `edges` are ranked after the nodes of `nodes`, in the order they first appear.
+///
+/// # Arguments
+///
+/// * `nodes` - The nodes of the graph, in the order used to break ties.
+/// * `edges` - The edges of the graph, from the node that must come first to the node that depends on it.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing:
+/// * `Ok(Vec<Node>)` - All the nodes, ordered so that every source comes before its destinations.
+/// * `Err(TopologicalSortError::CycleDetected)` - If the edges contain a cycle, with the components
+///   of the nodes that depend on each other and one cycle to break.
+pub fn topo_sort<Node: Hash + Eq + Copy>(
+    nodes: &[Node],
+    edges: &[(Node, Node)],
+) -> TopoSortResult<Node> {
+    // Step 1: Rank the nodes, so that ties are broken by their position
+    let mut ranked: Vec<Node> = Vec::new();
+    let mut rank_of: HashMap<Node, usize> = HashMap::new();
+    for node in nodes.iter().chain(
+        edges
+            .iter()
+            .flat_map(|(source, destination)| [source, destination]),
+    ) {
+        rank_of.entry(*node).or_insert_with(|| {
+            ranked.push(*node);
+            ranked.len() - 1
+        });
+    }
 
     // Step 2: Build the graph and count incoming edges for each node
+    let mut edges_by_source: Vec<Vec<usize>> = vec![Vec::new(); ranked.len()];
+    let mut incoming_edges_count: Vec<usize> = vec![0; ranked.len()];
     for (source, destination) in edges {
-        incoming_edges_count.entry(*source).or_insert(0);
-        edges_by_source
-            .entry(*source)
-            .or_default()
-            .push(*destination);
-        *incoming_edges_count.entry(*destination).or_insert(0) += 1;
+        let destination = rank_of[destination];
+        edges_by_source[rank_of[source]].push(destination);
+        incoming_edges_count[destination] += 1;
     }
 
     // Step 3: Find all nodes with no incoming edges
-    let mut no_incoming_edges_q = VecDeque::new();
-    for (node, count) in &incoming_edges_count {
-        if *count == 0 {
-            no_incoming_edges_q.push_back(*node);
+    let mut no_incoming_edges_q: BinaryHeap<Reverse<usize>> = (0..ranked.len())
+        .filter(|&rank| incoming_edges_count[rank] == 0)
+        .map(Reverse)
+        .collect();
+
+    // Step 4: Process the first ranked node with no incoming edges
+    let mut sorted = Vec::with_capacity(ranked.len());
+    while let Some(Reverse(rank)) = no_incoming_edges_q.pop() {
+        sorted.push(ranked[rank]);
+
+        // Step 5: Decrease the incoming edge count for each neighbor
+        for &neighbor in &edges_by_source[rank] {
+            incoming_edges_count[neighbor] -= 1;
+            if incoming_edges_count[neighbor] == 0 {
+                no_incoming_edges_q.push(Reverse(neighbor));
+            }
         }
     }
 
-    // Step 4: Process nodes with no incoming edges
-    let mut sorted = Vec::new();
-    while let Some(node) = no_incoming_edges_q.pop_back() {
-        sorted.push(node);
-        incoming_edges_count.remove(&node);
+    // Step 6: Check if there are any remaining nodes with incoming edges
+    if sorted.len() == ranked.len() {
+        return Ok(sorted);
+    }
 
-        // Step 5: Decrease the incoming edge count for each neighbor
-        if let Some(neighbors) = edges_by_source.get(&node) {
-            for &neighbor in neighbors {
-                if let Some(count) = incoming_edges_count.get_mut(&neighbor) {
-                    *count -= 1;
-                    if *count == 0 {
-                        no_incoming_edges_q.push_front(neighbor);
-                    }
+    // Step 7: Report the cycles among the remaining nodes
+    let remaining: Vec<usize> = (0..ranked.len())
+        .filter(|&rank| incoming_edges_count[rank] > 0)
+        .collect();
+    let components: Vec<Vec<usize>> = strongly_connected_components(&edges_by_source, &remaining)
+        .into_iter()
+        .filter(|component| {
+            component.len() > 1 || edges_by_source[component[0]].contains(&component[0])
+        })
+        .collect();
+    let cycle = shortest_cycle(&edges_by_source, &components[0]);
+    Err(TopologicalSortError::CycleDetected {
+        components: components
+            .iter()
+            .map(|component| component.iter().map(|&rank| ranked[rank]).collect())
+            .collect(),
+        cycle: cycle.into_iter().map(|rank| ranked[rank]).collect(),
+    })
+}
+
+/// Returns the strongly connected components of the graph induced by `nodes`, with Kosaraju's
+/// algorithm. The components and their nodes are sorted by rank.
+fn strongly_connected_components(
+    edges_by_source: &[Vec<usize>],
+    nodes: &[usize],
+) -> Vec<Vec<usize>> {
+    let mut in_graph = vec![false; edges_by_source.len()];
+    for &node in nodes {
+        in_graph[node] = true;
+    }
+    let mut edges_by_destination: Vec<Vec<usize>> = vec![Vec::new(); edges_by_source.len()];
+    for &source in nodes {
+        for &destination in &edges_by_source[source] {
+            if in_graph[destination] {
+                edges_by_destination[destination].push(source);
+            }
+        }
+    }
+
+    // The nodes in the order their depth-first search finishes, without recursion
+    let mut visited = vec![false; edges_by_source.len()];
+    let mut finished = Vec::with_capacity(nodes.len());
+    for &start in nodes {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some(&(node, next)) = stack.last() {
+            if let Some(&neighbor) = edges_by_source[node].get(next) {
+                stack.last_mut().unwrap().1 += 1;
+                if in_graph[neighbor] && !visited[neighbor] {
+                    visited[neighbor] = true;
+                    stack.push((neighbor, 0));
                 }
+            } else {
+                finished.push(node);
+                stack.pop();
             }
         }
     }
 
-    // Step 6: Check if there are any remaining nodes with incoming edges
-    if incoming_edges_count.is_empty() {
-        Ok(sorted)
-    } else {
-        Err(TopologicalSortError::CycleDetected)
+    // Each search on the reversed edges, in the reverse finishing order, finds one component
+    let mut assigned = vec![false; edges_by_source.len()];
+    let mut components = Vec::new();
+    for &start in finished.iter().rev() {
+        if assigned[start] {
+            continue;
+        }
+        assigned[start] = true;
+        let mut component = vec![start];
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for &neighbor in &edges_by_destination[node] {
+                if !assigned[neighbor] {
+                    assigned[neighbor] = true;
+                    component.push(neighbor);
+                    stack.push(neighbor);
+                }
+            }
+        }
+        component.sort_unstable();
+        components.push(component);
     }
+    components.sort_unstable();
+    components
+}
+
+/// Returns a shortest cycle through the first node of a strongly connected component, with a
+/// breadth-first search that stays in the component.
+fn shortest_cycle(edges_by_source: &[Vec<usize>], component: &[usize]) -> Vec<usize> {
+    let start = component[0];
+    let mut previous: HashMap<usize, usize> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for &neighbor in &edges_by_source[node] {
+            if neighbor == start {
+                // Walk back to the start to rebuild the path
+                let mut cycle = vec![start, node];
+                let mut current = node;
+                while current != start {
+                    current = previous[&current];
+                    cycle.push(current);
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if component.binary_search(&neighbor).is_ok() && !previous.contains_key(&neighbor) {
+                previous.insert(neighbor, node);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    vec![start]
 }
 
 #[cfg(test)]
@@ -84,7 +229,7 @@ mod tests {
     #[test]
     fn test_simple_graph() {
         let graph = vec![(1, 2), (1, 3), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7)];
-        let sort = topo_sort(&graph);
+        let sort = topo_sort(&[], &graph);
         assert!(sort.is_ok());
         let sort = sort.unwrap();
         assert!(is_valid_sort(&sort, &graph));
@@ -104,7 +249,7 @@ mod tests {
             (11, 10),
             (8, 9),
         ];
-        let sort = topo_sort(&graph);
+        let sort = topo_sort(&[], &graph);
         assert!(sort.is_ok());
         let sort = sort.unwrap();
         assert!(is_valid_sort(&sort, &graph));
@@ -113,8 +258,56 @@ mod tests {
     #[test]
     fn test_cyclic_graph() {
         let graph = vec![(1, 2), (2, 3), (3, 4), (4, 5), (4, 2)];
-        let sort = topo_sort(&graph);
+        let sort = topo_sort(&[], &graph);
         assert!(sort.is_err());
-        assert_eq!(sort.err().unwrap(), TopologicalSortError::CycleDetected);
+        assert_eq!(
+            sort.err().unwrap(),
+            TopologicalSortError::CycleDetected {
+                components: vec![vec![2, 3, 4]],
+                cycle: vec![2, 3, 4, 2],
+            }
+        );
+    }
+
+    #[test]
+    fn test_every_cycle_is_reported() {
+        let graph = vec![
+            (1, 2),
+            (2, 1),
+            (3, 3),
+            (4, 5),
+            (5, 6),
+            (6, 4),
+            (5, 4),
+            (6, 7),
+        ];
+        let sort = topo_sort(&[7, 6, 5, 4, 3, 2, 1], &graph);
+        assert_eq!(
+            sort.err().unwrap(),
+            TopologicalSortError::CycleDetected {
+                components: vec![vec![6, 5, 4], vec![3], vec![2, 1]],
+                cycle: vec![6, 4, 5, 6],
+            }
+        );
+    }
+
+    #[test]
+    fn test_isolated_nodes_are_kept() {
+        let graph = vec![(2, 3)];
+        let sort = topo_sort(&[1, 2, 3, 4], &graph).unwrap();
+        assert_eq!(sort, vec![1, 2, 3, 4]);
+        assert_eq!(topo_sort(&[1, 2], &[]).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_ties_are_broken_by_node_order() {
+        let graph = vec![(5, 11), (7, 11), (7, 8), (3, 8), (11, 2)];
+        let nodes = [2, 3, 5, 7, 8, 11];
+        let sort = topo_sort(&nodes, &graph).unwrap();
+        assert!(is_valid_sort(&sort, &graph));
+        assert_eq!(sort, vec![3, 5, 7, 8, 11, 2]);
+        for _ in 0..10 {
+            assert_eq!(topo_sort(&nodes, &graph).unwrap(), sort);
+        }
     }
 }